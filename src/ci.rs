@@ -0,0 +1,115 @@
+/// trailers linking a commit back to the CI job that made it, if running in one
+pub fn trailers() -> Vec<(String, String)> {
+    trailers_from(|name| std::env::var(name).ok())
+}
+
+fn trailers_from(env: impl Fn(&str) -> Option<String>) -> Vec<(String, String)> {
+    let env = |name: &str| env(name).filter(|value| !value.is_empty());
+
+    let trailers = if env("GITLAB_CI").is_some() {
+        [
+            ("Pipeline-URL", env("CI_PIPELINE_URL")),
+            ("Job-ID", env("CI_JOB_ID")),
+            ("Source-Repository", env("CI_PROJECT_URL")),
+            ("Source-Commit", env("CI_COMMIT_SHA")),
+        ]
+    } else if env("GITHUB_ACTIONS").is_some() {
+        // Gitea Actions sets the GitHub variables too, but links runs by number rather than ID
+        let run = match env("GITEA_ACTIONS") {
+            Some(_) => env("GITHUB_RUN_NUMBER"),
+            None => env("GITHUB_RUN_ID"),
+        };
+        let repository = env("GITHUB_SERVER_URL")
+            .zip(env("GITHUB_REPOSITORY"))
+            .map(|(server, repository)| format!("{server}/{repository}"));
+        [
+            (
+                "Pipeline-URL",
+                repository
+                    .as_ref()
+                    .zip(run)
+                    .map(|(repository, run)| format!("{repository}/actions/runs/{run}")),
+            ),
+            ("Job-ID", env("GITHUB_JOB")),
+            ("Source-Repository", repository),
+            ("Source-Commit", env("GITHUB_SHA")),
+        ]
+    } else {
+        return vec![];
+    };
+
+    trailers
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn trailers_with(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        trailers_from(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn test_trailers() {
+        assert!(trailers_with(&[]).is_empty());
+
+        assert_eq!(
+            trailers_with(&[
+                ("GITLAB_CI", "true"),
+                ("CI_PIPELINE_URL", "https://gitlab.com/app/-/pipelines/1"),
+                ("CI_JOB_ID", "2"),
+                ("CI_PROJECT_URL", "https://gitlab.com/app"),
+                ("CI_COMMIT_SHA", "abc"),
+            ]),
+            vec![
+                (
+                    "Pipeline-URL".into(),
+                    "https://gitlab.com/app/-/pipelines/1".into()
+                ),
+                ("Job-ID".into(), "2".into()),
+                ("Source-Repository".into(), "https://gitlab.com/app".into()),
+                ("Source-Commit".into(), "abc".into()),
+            ]
+        );
+
+        let github = [
+            ("GITHUB_ACTIONS", "true"),
+            ("GITHUB_SERVER_URL", "https://example.com"),
+            ("GITHUB_REPOSITORY", "org/app"),
+            ("GITHUB_RUN_ID", "100"),
+            ("GITHUB_RUN_NUMBER", "7"),
+            ("GITHUB_JOB", "build"),
+            ("GITHUB_SHA", "abc"),
+        ];
+        assert_eq!(
+            trailers_with(&github)[0],
+            (
+                "Pipeline-URL".into(),
+                "https://example.com/org/app/actions/runs/100".into()
+            )
+        );
+
+        let gitea = [&github[..], &[("GITEA_ACTIONS", "true")]].concat();
+        assert_eq!(
+            trailers_with(&gitea),
+            vec![
+                (
+                    "Pipeline-URL".into(),
+                    "https://example.com/org/app/actions/runs/7".into()
+                ),
+                ("Job-ID".into(), "build".into()),
+                (
+                    "Source-Repository".into(),
+                    "https://example.com/org/app".into()
+                ),
+                ("Source-Commit".into(), "abc".into()),
+            ]
+        );
+    }
+}
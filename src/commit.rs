@@ -1,28 +1,145 @@
+use anyhow::{anyhow, Error};
 use bytes::Bytes;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
 
 pub type FileList = HashMap<String, Bytes>;
 
+/// the changes computed by the templaters
+#[derive(Clone, Debug, Default)]
+pub struct Changeset {
+    pub files: FileList,
+    /// paths of the files to remove
+    pub deleted: HashSet<String>,
+    /// files to rename, from their new path to their previous one
+    pub moved: HashMap<String, String>,
+    /// the values changed by templaters that patch files, secrets excluded
+    pub values: Vec<ValueChange>,
+}
+
+/// a value changed in a file, as exposed to commit message templates
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ValueChange {
+    pub file: String,
+    pub key: String,
+    /// none if the value didn't exist or wasn't a scalar
+    pub old: Option<String>,
+    pub new: String,
+}
+
+impl Changeset {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.deleted.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// a git identity, parsed from 'name <email>'
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("invalid identity '{s}', expected 'name <email>'");
+        let (name, email) = s.trim().split_once('<').ok_or_else(invalid)?;
+        let name = name.trim();
+        let email = email.strip_suffix('>').ok_or_else(invalid)?;
+        let valid_email = match email.split_once('@') {
+            Some((user, domain)) => {
+                !user.is_empty()
+                    && !domain.is_empty()
+                    && !domain.contains('@')
+                    && !email.contains(|c: char| c.is_whitespace() || c == '<' || c == '>')
+            }
+            None => false,
+        };
+        if name.is_empty() || name.contains('>') || !valid_email {
+            return Err(invalid());
+        }
+        Ok(Self {
+            name: name.into(),
+            email: email.into(),
+        })
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} <{}>", self.name, self.email)
+    }
+}
+
+/// appends git trailers (such as `Co-authored-by: name <email>`) to a commit message
+pub fn with_trailers(message: &str, trailers: &[(String, String)]) -> String {
+    if trailers.is_empty() {
+        return message.into();
+    }
+    let trailers: Vec<String> = trailers
+        .iter()
+        .map(|(key, value)| format!("{key}: {value}"))
+        .collect();
+    format!("{}\n\n{}", message.trim_end(), trailers.join("\n"))
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct CommitRequest {
     pub branch: String,
-    pub author: String,
+    /// the branch to create branch from, if it doesn't exist yet
+    pub start_branch: Option<String>,
+    pub author: Identity,
+    /// defaults to the author, not every provider supports it
+    pub committer: Option<Identity>,
     pub message: String,
+    /// files to create or update, including the new content of moved files
     pub files: FileList,
+    pub deleted: HashSet<String>,
+    pub moved: HashMap<String, String>,
 }
 
 impl CommitRequest {
-    /// split_author splits the author field to return a tuple of (name, email) fields.
-    pub fn split_author(&self) -> (String, String) {
-        match self.author.split_once('<') {
-            None => (self.author.to_string(), "".to_string()),
-            Some((name, email)) => (
-                name.trim().to_string(),
-                email
-                    .trim_matches(|c: char| c.is_whitespace() || c == '<' || c == '>')
-                    .to_string(),
-            ),
-        }
+    /// the paths touched by the commit, sorted
+    pub fn paths(&self) -> Vec<String> {
+        self.files
+            .keys()
+            .chain(self.deleted.iter())
+            .chain(self.moved.values())
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// the outcome of a run, for downstream CI steps
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CommitInfo {
+    /// empty when there was nothing to commit
+    pub sha: String,
+    pub web_url: String,
+    pub files: Vec<String>,
+    pub merge_request_url: Option<String>,
+    pub release_url: Option<String>,
+}
+
+impl CommitInfo {
+    /// formats the info as `key=value` lines, as expected by $GITHUB_OUTPUT or dotenv files
+    pub fn to_env(&self) -> String {
+        format!(
+            "sha={}\nweb_url={}\nfiles={}\nmerge_request_url={}\nrelease_url={}\n",
+            self.sha,
+            self.web_url,
+            self.files.join(","),
+            self.merge_request_url.as_deref().unwrap_or_default(),
+            self.release_url.as_deref().unwrap_or_default()
+        )
     }
 }
 
@@ -31,35 +148,79 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_split_author() {
-        let (name, email) = ("test-author", "author@example.com");
-        let combined = format!("{} <{}>", name, email);
-
+    fn test_parse_identity() {
         assert_eq!(
-            CommitRequest {
-                author: combined,
-                ..Default::default()
+            "test-author <author@example.com>"
+                .parse::<Identity>()
+                .unwrap(),
+            Identity {
+                name: "test-author".into(),
+                email: "author@example.com".into(),
+            }
+        );
+        assert_eq!(
+            " Jane Doe<jane@example.com> ".parse::<Identity>().unwrap(),
+            Identity {
+                name: "Jane Doe".into(),
+                email: "jane@example.com".into(),
             }
-            .split_author(),
-            (name.to_string(), email.to_string())
         );
 
+        for invalid in [
+            "test-author",
+            "<author@example.com>",
+            "test-author <author@example.com",
+            "test-author <author@example.com> trailing",
+            "test-author <author>",
+            "test-author <@example.com>",
+            "test-author <author @example.com>",
+            "test-author <author@example.com@other>",
+        ] {
+            assert!(invalid.parse::<Identity>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_with_trailers() {
+        assert_eq!(with_trailers("Update deployment", &[]), "Update deployment");
+        assert_eq!(
+            with_trailers(
+                "Update deployment\n",
+                &[
+                    ("Co-authored-by".into(), "test <test@example.com>".into()),
+                    ("Source-Commit".into(), "abc".into()),
+                ]
+            ),
+            "Update deployment\n\nCo-authored-by: test <test@example.com>\nSource-Commit: abc"
+        );
+    }
+
+    #[test]
+    fn test_paths() {
         assert_eq!(
             CommitRequest {
-                author: name.to_string(),
+                files: FileList::from([("b.txt".into(), "b".into()), ("c.txt".into(), "c".into())]),
+                deleted: HashSet::from(["a.txt".into()]),
+                moved: HashMap::from([("c.txt".into(), "d.txt".into())]),
                 ..Default::default()
             }
-            .split_author(),
-            (name.to_string(), "".into())
+            .paths(),
+            vec!["a.txt", "b.txt", "c.txt", "d.txt"]
         );
+    }
 
+    #[test]
+    fn test_commit_info_to_env() {
         assert_eq!(
-            CommitRequest {
-                author: format!("<{}>", email),
-                ..Default::default()
+            CommitInfo {
+                sha: "abc".into(),
+                web_url: "https://example.com/commit/abc".into(),
+                files: vec!["a.txt".into(), "b.txt".into()],
+                merge_request_url: None,
+                release_url: None,
             }
-            .split_author(),
-            ("".into(), email.to_string())
+            .to_env(),
+            "sha=abc\nweb_url=https://example.com/commit/abc\nfiles=a.txt,b.txt\nmerge_request_url=\nrelease_url=\n"
         );
     }
 }
@@ -0,0 +1,219 @@
+use std::{io::Read, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use regex::{Captures, Regex};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_yaml::Value;
+
+use crate::{
+    providers::{self, Provider},
+    templaters::Mutation,
+};
+
+/// a shipit.yaml file, whose settings are overridden by the corresponding flags
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// either a mapping or a connection string
+    #[serde(default, deserialize_with = "provider")]
+    pub provider: Option<Provider>,
+    pub branch: Option<String>,
+    pub start_branch: Option<String>,
+    /// as 'name <email>'
+    pub author: Option<String>,
+    pub message: Option<String>,
+    pub changes: Option<Vec<Mutation>>,
+}
+
+fn provider<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Provider>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Spec {
+        Url(String),
+        Info(Provider),
+    }
+
+    Ok(match Spec::deserialize(deserializer)? {
+        Spec::Url(url) => Some(providers::parse(&url).map_err(serde::de::Error::custom)?),
+        Spec::Info(provider) => Some(provider),
+    })
+}
+
+/// reads a file, or stdin if path is `-`
+fn read(path: &Path) -> Result<String> {
+    let mut content = String::new();
+    if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut content)?;
+    } else {
+        content = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+    }
+    Ok(content)
+}
+
+/// parses YAML or JSON, interpolating environment variables in its string values
+fn parse<T: DeserializeOwned>(text: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<T> {
+    let mut value: Value = serde_yaml::from_str(text)?;
+    interpolate_value(&mut value, env)?;
+    Ok(serde_yaml::from_value(value)?)
+}
+
+pub fn load(path: &Path) -> Result<Config> {
+    parse(&read(path)?, &|name| std::env::var(name).ok())
+        .with_context(|| format!("invalid config file {}", path.display()))
+}
+
+/// loads a list of mutations from a YAML or JSON file
+pub fn load_changes(path: &Path) -> Result<Vec<Mutation>> {
+    parse(&read(path)?, &|name| std::env::var(name).ok())
+        .with_context(|| format!("invalid changeset file {}", path.display()))
+}
+
+/// interpolates the string scalars of a document, leaving its keys, comments and structure alone
+fn interpolate_value(value: &mut Value, env: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+    match value {
+        Value::String(text) => *text = interpolate(text, env)?,
+        Value::Sequence(items) => {
+            for item in items {
+                interpolate_value(item, env)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for item in mapping.values_mut() {
+                interpolate_value(item, env)?;
+            }
+        }
+        Value::Tagged(tagged) => interpolate_value(&mut tagged.value, env)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// replaces `${NAME}` and `${NAME:-default}` with environment variables, `$$` escapes a `$`
+fn interpolate(text: &str, env: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let pattern = Regex::new(r"\$\$|\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}")?;
+
+    let mut missing = vec![];
+    let interpolated = pattern.replace_all(text, |captures: &Captures| {
+        let Some(name) = captures.get(1) else {
+            return "$".to_string();
+        };
+        match (env(name.as_str()), captures.get(2)) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default.as_str().into(),
+            (None, None) => {
+                missing.push(name.as_str().to_string());
+                String::new()
+            }
+        }
+    });
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "missing environment variables: {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(interpolated.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let env = HashMap::from([("TAG", "1.5.0"), ("EMPTY", "")]);
+        let env = |name: &str| env.get(name).map(|value| value.to_string());
+
+        assert_eq!(
+            interpolate(
+                "tag: ${TAG}, env: ${ENVIRONMENT:-staging}, empty: '${EMPTY}', price: $$5, shell: $TAG",
+                env
+            )
+            .unwrap(),
+            "tag: 1.5.0, env: staging, empty: '', price: $5, shell: $TAG"
+        );
+        assert_eq!(
+            interpolate("${MISSING} ${OTHER}", env)
+                .unwrap_err()
+                .to_string(),
+            "missing environment variables: MISSING, OTHER"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_strings_only() {
+        let env = HashMap::from([("TAG", "1.5.0\nbranch: injected"), ("BRANCH", "production")]);
+        let env = |name: &str| env.get(name).map(|value| value.to_string());
+
+        let config: Config = parse(
+            r#"
+# ${MISSING} in a comment is left alone
+branch: ${BRANCH}
+changes:
+  - templater: yaml
+    file: values.yaml
+    changes:
+      image.tag: ${TAG}
+  - templater: file
+    file: run.sh
+    content: |
+      #!/bin/sh
+      echo "${BRANCH:-main}" $$HOME
+"#,
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(config.branch.as_deref(), Some("production"));
+        let changes = config.changes.unwrap();
+        match &changes[0] {
+            Mutation::Yaml { changes, .. } => {
+                assert_eq!(changes["image.tag"], "1.5.0\nbranch: injected")
+            }
+            _ => panic!("unexpected mutation"),
+        }
+        match &changes[1] {
+            Mutation::File { source, .. } => assert_eq!(
+                source.load().unwrap(),
+                "#!/bin/sh\necho \"production\" $HOME\n"
+            ),
+            _ => panic!("unexpected mutation"),
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+provider:
+  provider: gitlab
+  project_id: group/gitops
+  token: secret
+branch: production
+author: shipit <shipit@example.com>
+changes:
+  - templater: yaml
+    file: values.yaml
+    changes:
+      image.tag: "1.5.0"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.provider.unwrap().name(), "gitlab");
+        assert_eq!(config.branch.as_deref(), Some("production"));
+        assert_eq!(config.changes.unwrap().len(), 1);
+        assert!(config.message.is_none());
+
+        assert!(serde_yaml::from_str::<Config>("unknown: true").is_err());
+
+        let config: Config =
+            serde_yaml::from_str("provider: gitea://localhost/owner/repo?token=file:/dev/null")
+                .unwrap();
+        assert_eq!(config.provider.unwrap().name(), "gitea");
+    }
+}
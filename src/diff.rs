@@ -0,0 +1,248 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use bytes::Bytes;
+use similar::TextDiff;
+
+use crate::{commit::CommitRequest, repository::Repository};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// a single file as it was before the commit and as it would be after
+struct FileDiff {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    old: Option<Bytes>,
+    new: Option<Bytes>,
+}
+
+fn paint(output: &mut String, color: Option<&str>, line: &str) {
+    match color {
+        Some(color) => writeln!(output, "{color}{line}{RESET}"),
+        None => writeln!(output, "{line}"),
+    }
+    .expect("writing to a String cannot fail");
+}
+
+impl FileDiff {
+    fn render(&self, output: &mut String, color: bool) {
+        let color = |code| Some(code).filter(|_| color);
+        let old_path = self
+            .old_path
+            .as_ref()
+            .map_or("/dev/null".into(), |path| format!("a/{path}"));
+        let new_path = self
+            .new_path
+            .as_ref()
+            .map_or("/dev/null".into(), |path| format!("b/{path}"));
+
+        let old = self.old.as_deref().unwrap_or_default();
+        let new = self.new.as_deref().unwrap_or_default();
+        let moved =
+            self.old_path.is_some() && self.new_path.is_some() && self.old_path != self.new_path;
+        if moved && old == new {
+            // moved without changes
+            paint(
+                output,
+                color(BOLD),
+                &format!("rename {old_path} => {new_path}"),
+            );
+            return;
+        }
+
+        let (Ok(old), Ok(new)) = (std::str::from_utf8(old), std::str::from_utf8(new)) else {
+            paint(
+                output,
+                color(BOLD),
+                &format!("Binary files {old_path} and {new_path} differ"),
+            );
+            return;
+        };
+
+        paint(output, color(BOLD), &format!("--- {old_path}"));
+        paint(output, color(BOLD), &format!("+++ {new_path}"));
+        let diff = TextDiff::from_lines(old, new);
+        for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+            for line in hunk.to_string().lines() {
+                let code = match line.chars().next() {
+                    Some('+') => color(GREEN),
+                    Some('-') => color(RED),
+                    Some('@') => color(CYAN),
+                    _ => None,
+                };
+                paint(output, code, line);
+            }
+        }
+    }
+}
+
+/// renders the commit that would be created as a unified diff against the branch
+pub fn render(repository: &dyn Repository, commit: &CommitRequest, color: bool) -> Result<String> {
+    let reference = commit.start_branch.as_deref().unwrap_or(&commit.branch);
+    let original = |path: &str| repository.get(path, reference).ok();
+
+    let mut files: Vec<FileDiff> = commit
+        .moved
+        .iter()
+        .map(|(to, from)| {
+            let old = original(from);
+            FileDiff {
+                old_path: Some(from.clone()),
+                new_path: Some(to.clone()),
+                new: commit.files.get(to).cloned().or_else(|| old.clone()),
+                old,
+            }
+        })
+        .collect();
+    files.extend(
+        commit
+            .files
+            .iter()
+            .filter(|(file, _)| !commit.moved.contains_key(*file))
+            .map(|(file, content)| {
+                let old = original(file);
+                FileDiff {
+                    old_path: old.as_ref().map(|_| file.clone()),
+                    new_path: Some(file.clone()),
+                    old,
+                    new: Some(content.clone()),
+                }
+            }),
+    );
+    files.extend(commit.deleted.iter().map(|file| FileDiff {
+        old_path: Some(file.clone()),
+        new_path: None,
+        old: original(file),
+        new: None,
+    }));
+    files.sort_by(|a, b| {
+        (a.new_path.as_ref().or(a.old_path.as_ref()))
+            .cmp(&b.new_path.as_ref().or(b.old_path.as_ref()))
+    });
+
+    let mut output = String::new();
+    match &commit.start_branch {
+        Some(start_branch) => writeln!(
+            output,
+            "branch: {} (new, from {})",
+            commit.branch, start_branch
+        )?,
+        None => writeln!(output, "branch: {}", commit.branch)?,
+    }
+    writeln!(output, "author: {}", commit.author)?;
+    if let Some(committer) = &commit.committer {
+        writeln!(output, "committer: {committer}")?;
+    }
+    writeln!(output, "message: {}", commit.message)?;
+    for file in files {
+        writeln!(output)?;
+        file.render(&mut output, color);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::{commit::FileList, repository::InMemoryRepository};
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut repo = InMemoryRepository::default();
+        repo.commit(CommitRequest {
+            branch: "main".into(),
+            files: FileList::from([
+                ("values.yaml".into(), "image:\n  tag: \"1.0\"\n".into()),
+                ("old.txt".into(), "old\n".into()),
+                ("moved.txt".into(), "moved\n".into()),
+            ]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let output = render(
+            &repo,
+            &CommitRequest {
+                branch: "main".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                files: FileList::from([
+                    ("values.yaml".into(), "image:\n  tag: \"2.0\"\n".into()),
+                    ("new.txt".into(), "new\n".into()),
+                ]),
+                deleted: HashSet::from(["old.txt".into()]),
+                moved: HashMap::from([("renamed.txt".into(), "moved.txt".into())]),
+                ..Default::default()
+            },
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            r#"branch: main
+author: test <author@email.tld>
+message: test
+
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+
+rename a/moved.txt => b/renamed.txt
+
+--- a/values.yaml
++++ b/values.yaml
+@@ -1,2 +1,2 @@
+ image:
+-  tag: "1.0"
++  tag: "2.0"
+"#
+        );
+    }
+
+    #[test]
+    fn test_render_empty_files() {
+        let repo = InMemoryRepository::default();
+
+        let output = render(
+            &repo,
+            &CommitRequest {
+                branch: "main".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                files: FileList::from([("empty.txt".into(), "".into())]),
+                deleted: HashSet::from(["missing.txt".into()]),
+                ..Default::default()
+            },
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            r#"branch: main
+author: test <author@email.tld>
+message: test
+
+--- /dev/null
++++ b/empty.txt
+
+--- a/missing.txt
++++ /dev/null
+"#
+        );
+    }
+}
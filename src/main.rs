@@ -1,85 +1,729 @@
+use std::{
+    ffi::OsString,
+    fs::OpenOptions,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
 use anyhow::{anyhow, Result};
-use clap::{arg, command};
-use commit::CommitRequest;
+use clap::{arg, command, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command};
+use commit::{with_trailers, CommitInfo, CommitRequest, Identity};
+use config::Config;
+use merge_request::MergeRequest;
 use providers::{get_repository, Provider};
-use templaters::{mutate, Mutation};
+use repository::{Conflict, Repository};
+use status::CommitStatus;
+use tag::{Release, Tag};
+use templaters::{from_assignments, mutate, Mutation};
 
+mod ci;
 mod commit;
+mod config;
+mod diff;
+mod merge_request;
+mod message;
 mod providers;
 mod repository;
+mod status;
+mod tag;
 mod templaters;
 
 struct Task {
-    pub provider: Provider,
     pub changes: Vec<Mutation>,
     pub branch: String,
-    pub author: String,
+    pub start_branch: Option<String>,
+    pub author: Identity,
+    pub committer: Option<Identity>,
+    /// the commit message template
     pub message: String,
+    /// trailers appended to the commit message
+    pub trailers: Vec<(String, String)>,
+    pub dry_run: bool,
+    pub retries: u32,
+    pub merge_request: Option<MergeRequest>,
+    pub auto_merge: bool,
+    /// how long to wait for the merge request to be merged, if at all
+    pub wait: Option<Duration>,
+    pub tag: Option<Tag>,
+    pub release: Option<Release>,
+    pub status: Option<CommitStatus>,
+    /// the commit to set the status on, instead of the resulting one
+    pub status_sha: Option<String>,
+    pub output: OutputFormat,
+    /// file to append the result to as `key=value` lines
+    pub output_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// how often to check the merge request state while waiting
+const WAIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// the value of a flag, unless it's the default one
+fn explicit<'a, T: Clone + Send + Sync + 'static>(
+    matches: &'a ArgMatches,
+    id: &str,
+) -> Option<&'a T> {
+    matches
+        .get_one::<T>(id)
+        .filter(|_| matches.value_source(id) != Some(ValueSource::DefaultValue))
+}
+
+/// whether a flag is set, false for flags the subcommand doesn't accept
+fn flag(matches: &ArgMatches, id: &str) -> bool {
+    matches.try_get_one::<bool>(id).ok().flatten() == Some(&true)
+}
+
+/// the value of a flag, None for flags the subcommand doesn't accept
+fn optional<'a, T: Clone + Send + Sync + 'static>(
+    matches: &'a ArgMatches,
+    id: &str,
+) -> Option<&'a T> {
+    matches.try_get_one::<T>(id).ok().flatten()
+}
+
+/// collects a repeatable, comma-delimited flag
+fn many(matches: &ArgMatches, id: &str) -> Vec<String> {
+    matches
+        .get_many::<String>(id)
+        .unwrap_or_default()
+        .filter(|value| !value.is_empty())
+        .cloned()
+        .collect()
+}
+
+fn config_arg() -> Arg {
+    arg!(--config <path> "Config file declaring the provider, branch, author, message and changes (as YAML)")
+        .env("SHIPIT_CONFIG")
+        .value_parser(value_parser!(PathBuf))
+}
+
+/// arguments selecting the repository and branch
+fn repository_args() -> Vec<Arg> {
+    vec![
+        config_arg(),
+        arg!(-p --provider <provider> "Provider info (as JSON, as a URL like gitea://host/owner/repo?token=env:NAME, or auto in CI)")
+            .env("SHIPIT_PROVIDER"),
+        arg!(-b --branch <branch> "Branch to read from and commit to")
+            .env("SHIPIT_BRANCH")
+            .default_value("main"),
+    ]
+}
+
+/// arguments declaring the changes to apply
+fn changes_args() -> Vec<Arg> {
+    vec![
+        arg!(-c --changeset <changes> "Changes to apply (as JSON)").env("SHIPIT_CHANGES"),
+        arg!(--"changeset-file" <path> "File with the changes to apply (as YAML or JSON, - for stdin)")
+            .env("SHIPIT_CHANGESET_FILE")
+            .value_parser(value_parser!(PathBuf))
+            .conflicts_with("changeset"),
+        arg!(--set <assignment> "Change a value, as templater:file:path=value (e.g. yaml:values.yaml:image.tag=1.0)")
+            .action(ArgAction::Append),
+    ]
+}
+
+/// arguments controlling the commit
+fn commit_args() -> Vec<Arg> {
+    vec![
+        arg!(-a --author <author> "Commit author (as 'name <email>')")
+            .env("SHIPIT_AUTHOR")
+            .value_parser(value_parser!(Identity))
+            .default_value("shipit <shipit@localhost>"),
+        arg!(--committer <committer> "Commit committer, if different from the author (as 'name <email>')")
+            .env("SHIPIT_COMMITTER")
+            .value_parser(value_parser!(Identity)),
+        arg!(--"co-author" <author> "Co-author to credit with a Co-authored-by trailer (as 'name <email>')")
+            .env("SHIPIT_CO_AUTHORS")
+            .value_parser(value_parser!(Identity))
+            .action(ArgAction::Append)
+            .value_delimiter(','),
+        arg!(--"no-ci-trailers" "Don't add trailers linking the commit to the CI job")
+            .env("SHIPIT_NO_CI_TRAILERS")
+            .action(ArgAction::SetTrue),
+        arg!(--"start-branch" <ref> "Branch to create the branch from if it doesn't exist")
+            .visible_alias("base")
+            .env("SHIPIT_START_BRANCH"),
+        arg!(-m --message <message> "Commit message, as a template with access to branch, files, changes and env (CI_* and SHIPIT_* variables, without credentials)")
+            .env("SHIPIT_MESSAGE")
+            .default_value("Update deployment"),
+        arg!(--retries <retries> "How many times to retry when the branch changes while committing")
+            .env("SHIPIT_RETRIES")
+            .value_parser(value_parser!(u32))
+            .default_value("3"),
+        arg!(-o --output <format> "How to print the result")
+            .env("SHIPIT_OUTPUT")
+            .value_parser(["text", "json"])
+            .default_value("text"),
+    ]
+}
+
+/// arguments controlling what's done after the commit, which only `commit` accepts
+fn publish_args() -> Vec<Arg> {
+    vec![
+        arg!(--"create-merge-request" "Commit to a separate branch and open a merge request into --branch")
+            .env("SHIPIT_CREATE_MERGE_REQUEST")
+            .action(ArgAction::SetTrue),
+        arg!(--"source-branch" <branch> "Branch to commit to when opening a merge request (default: shipit/<branch>), recreated when it has no open merge request")
+            .env("SHIPIT_SOURCE_BRANCH"),
+        arg!(--"mr-title" <title> "Merge request title (default: the commit message subject)").env("SHIPIT_MR_TITLE"),
+        arg!(--"mr-description" <description> "Merge request description")
+            .env("SHIPIT_MR_DESCRIPTION")
+            .default_value(""),
+        arg!(--"mr-label" <label> "Label to add to the merge request")
+            .env("SHIPIT_MR_LABELS")
+            .action(ArgAction::Append)
+            .value_delimiter(','),
+        arg!(--"mr-reviewer" <username> "Reviewer to request on the merge request")
+            .env("SHIPIT_MR_REVIEWERS")
+            .action(ArgAction::Append)
+            .value_delimiter(','),
+        arg!(--"mr-assignee" <username> "User to assign the merge request to")
+            .env("SHIPIT_MR_ASSIGNEES")
+            .action(ArgAction::Append)
+            .value_delimiter(','),
+        arg!(--"auto-merge" "Merge the merge request as soon as its pipeline succeeds")
+            .env("SHIPIT_AUTO_MERGE")
+            .requires("create-merge-request")
+            .action(ArgAction::SetTrue),
+        arg!(--wait "Wait until the merge request is merged, failing if it's closed or its checks fail")
+            .env("SHIPIT_WAIT")
+            .requires("create-merge-request")
+            .action(ArgAction::SetTrue),
+        arg!(--"wait-timeout" <duration> "How long to wait for the merge request to be merged")
+            .env("SHIPIT_WAIT_TIMEOUT")
+            .value_parser(humantime::parse_duration)
+            .default_value("30m"),
+        arg!(--tag <name> "Tag to create on the resulting commit, or the merged one with --create-merge-request and --wait").env("SHIPIT_TAG"),
+        arg!(--"tag-message" <message> "Create an annotated tag with this message")
+            .env("SHIPIT_TAG_MESSAGE")
+            .requires("tag"),
+        arg!(--release "Create a release for the tag")
+            .env("SHIPIT_RELEASE")
+            .requires("tag")
+            .action(ArgAction::SetTrue),
+        arg!(--"release-name" <name> "Release name (default: the tag)")
+            .env("SHIPIT_RELEASE_NAME")
+            .requires("release"),
+        arg!(--"release-notes" <notes> "Release notes")
+            .env("SHIPIT_RELEASE_NOTES")
+            .requires("release"),
+        arg!(--status <state> "Commit status to set on the resulting commit")
+            .env("SHIPIT_STATUS")
+            .value_parser(["pending", "success", "failure"]),
+        arg!(--"status-context" <name> "Name identifying the commit status")
+            .env("SHIPIT_STATUS_CONTEXT")
+            .default_value("shipit"),
+        arg!(--"status-url" <url> "URL the commit status links to").env("SHIPIT_STATUS_URL"),
+        arg!(--"status-description" <description> "Commit status description")
+            .env("SHIPIT_STATUS_DESCRIPTION"),
+        arg!(--"status-sha" <sha> "Set the commit status on this commit instead")
+            .env("SHIPIT_STATUS_SHA")
+            .requires("status"),
+        arg!(--"output-file" <path> "Append the result as key=value lines to a file, such as $GITHUB_OUTPUT")
+            .env("SHIPIT_OUTPUT_FILE")
+            .value_parser(value_parser!(PathBuf)),
+    ]
+}
+
+/// runs `commit` when no subcommand is given, as shipit did before having subcommands
+fn args() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let subcommand = args.get(1).and_then(|arg| arg.to_str());
+    if !matches!(
+        subcommand,
+        Some(
+            "get" | "diff" | "commit" | "validate" | "help" | "-h" | "--help" | "-V" | "--version"
+        )
+    ) {
+        args.insert(1, "commit".into());
+    }
+    args
 }
 
 fn main() -> Result<()> {
     env_logger::init_from_env(env_logger::Env::new().filter("SHIPIT_LOG"));
 
     let matches = command!()
-        .arg(arg!(-p --provider <provider> "Provider info (as JSON)").env("SHIPIT_PROVIDER"))
-        .arg(arg!(-c --changeset <changes> "Changes to apply (as JSON)").env("SHIPIT_CHANGES"))
-        .arg(
-            arg!(-a --author <author> "Commit author (as 'name <email>')")
-                .env("SHIPIT_AUTHOR")
-                .default_value("shipit"),
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("get")
+                .about("Print a file from the repository, or a single value in it")
+                .args(repository_args())
+                .arg(arg!(<file> "Path of the file in the repository"))
+                .arg(arg!(--path <path> "Path of the value to print, as used by the templaters"))
+                .arg(
+                    arg!(--templater <templater> "Templater to read the value with (default: guessed from the file name)")
+                        .value_parser(["json", "yaml", "dockerfile"]),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Print the changes as a diff without committing them")
+                .args(repository_args())
+                .args(changes_args())
+                .args(commit_args()),
         )
-        .arg(
-            arg!(-b --branch <branch> "Branch to commit to")
-                .env("SHIPIT_BRANCH")
-                .default_value("main"),
+        .subcommand(
+            Command::new("commit")
+                .about("Commit the changes (default)")
+                .args(repository_args())
+                .args(changes_args())
+                .args(commit_args())
+                .args(publish_args())
+                .arg(
+                    arg!(--"dry-run" "Print the changes as a diff instead of committing them")
+                        .env("SHIPIT_DRY_RUN")
+                        .action(ArgAction::SetTrue),
+                ),
         )
-        .arg(
-            arg!(-m --message <message> "Commit message")
-                .env("SHIPIT_MESSAGE")
-                .default_value("Update deployment"),
+        .subcommand(
+            Command::new("validate")
+                .about("Check the changes without connecting to the provider")
+                .arg(config_arg())
+                .args(changes_args()),
         )
-        .get_matches();
+        .get_matches_from(args());
+
+    match matches.subcommand() {
+        Some(("get", matches)) => get(matches),
+        Some(("diff", matches)) => commit(matches, true),
+        Some(("commit", matches)) => commit(matches, matches.get_flag("dry-run")),
+        Some(("validate", matches)) => validate(matches),
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+fn load_config(matches: &ArgMatches) -> Result<Config> {
+    match matches.get_one::<PathBuf>("config") {
+        Some(path) => config::load(path),
+        None => Ok(Config::default()),
+    }
+}
+
+/// the target branch, from the flags or the config file
+fn branch(matches: &ArgMatches, file: &Config) -> String {
+    explicit::<String>(matches, "branch")
+        .or(file.branch.as_ref())
+        .or(matches.get_one::<String>("branch"))
+        .unwrap()
+        .clone()
+}
+
+fn provider(matches: &ArgMatches, file: &mut Config) -> Result<Provider> {
+    Ok(match matches.get_one::<String>("provider") {
+        Some(provider) => providers::parse(provider)?,
+        None => file.provider.take().ok_or_else(|| {
+            anyhow!("Missing provider info (--provider, SHIPIT_PROVIDER or config file)")
+        })?,
+    })
+}
+
+fn load_changes(matches: &ArgMatches, file: &mut Config) -> Result<Vec<Mutation>> {
+    // assignments are applied after the other changes
+    let assignments = from_assignments(
+        &matches
+            .get_many::<String>("set")
+            .unwrap_or_default()
+            .cloned()
+            .collect::<Vec<_>>(),
+    )?;
+    let mut changes: Vec<Mutation> = match (
+        matches.get_one::<String>("changeset"),
+        matches.get_one::<PathBuf>("changeset-file"),
+    ) {
+        (Some(changes), _) => serde_json::from_str(changes)?,
+        (None, Some(path)) => config::load_changes(path)?,
+        (None, None) if !assignments.is_empty() => file.changes.take().unwrap_or_default(),
+        (None, None) => file.changes.take().ok_or_else(|| {
+            anyhow!("Missing changeset (--changeset, --changeset-file, --set, SHIPIT_CHANGES or config file)")
+        })?,
+    };
+    changes.extend(assignments);
+
+    Ok(changes)
+}
+
+fn get(matches: &ArgMatches) -> Result<()> {
+    let mut file = load_config(matches)?;
+    let branch = branch(matches, &file);
+    let repo = get_repository(provider(matches, &mut file)?)?;
+
+    let path = matches.get_one::<String>("file").unwrap();
+    let content = repo.get(path, &branch)?;
+
+    let Some(key) = matches.get_one::<String>("path") else {
+        std::io::stdout().write_all(&content)?;
+        return Ok(());
+    };
+    let templater = matches
+        .get_one::<String>("templater")
+        .map(String::as_str)
+        .or_else(|| templaters::templater_for(path))
+        .ok_or_else(|| anyhow!("cannot guess the templater for {path}, use --templater"))?;
+    let value = templaters::value(templater, &content, key)?
+        .ok_or_else(|| anyhow!("{key} not found in {path}"))?;
+    println!("{value}");
+
+    Ok(())
+}
+
+fn validate(matches: &ArgMatches) -> Result<()> {
+    let mut file = load_config(matches)?;
+    let changes = load_changes(matches, &mut file)?;
+
+    let mut invalid = 0;
+    for (index, mutation) in changes.iter().enumerate() {
+        if let Err(err) = mutation.validate() {
+            invalid += 1;
+            eprintln!("change #{}: {:#}", index + 1, err);
+        }
+    }
+    if invalid > 0 {
+        return Err(anyhow!(
+            "{invalid} of {} changes are invalid",
+            changes.len()
+        ));
+    }
+
+    println!("{} changes are valid", changes.len());
+    Ok(())
+}
+
+fn commit(matches: &ArgMatches, dry_run: bool) -> Result<()> {
+    let mut file = load_config(matches)?;
+
+    // flags override the config file, which overrides the flags' defaults
+    let target_branch = branch(matches, &file);
+    let message: String = explicit::<String>(matches, "message")
+        .or(file.message.as_ref())
+        .or(matches.get_one::<String>("message"))
+        .unwrap()
+        .clone();
+    let author: Identity = match (explicit::<Identity>(matches, "author"), &file.author) {
+        (None, Some(author)) => author.parse()?,
+        _ => matches.get_one::<Identity>("author").unwrap().clone(),
+    };
+    let mut trailers: Vec<(String, String)> = matches
+        .get_many::<Identity>("co-author")
+        .unwrap_or_default()
+        .map(|co_author| ("Co-authored-by".into(), co_author.to_string()))
+        .collect();
+    if !matches.get_flag("no-ci-trailers") {
+        trailers.extend(ci::trailers());
+    }
+
+    // the merge request's commit only lands on the target branch once merged, so that's what gets tagged
+    if flag(matches, "create-merge-request")
+        && optional::<String>(matches, "tag").is_some()
+        && !flag(matches, "wait")
+    {
+        return Err(anyhow!("--tag with --create-merge-request requires --wait"));
+    }
+
+    let merge_request = flag(matches, "create-merge-request").then(|| MergeRequest {
+        source_branch: matches
+            .get_one::<String>("source-branch")
+            .cloned()
+            .unwrap_or_else(|| format!("shipit/{target_branch}")),
+        target_branch: target_branch.clone(),
+        // defaults to the rendered commit message
+        title: matches
+            .get_one::<String>("mr-title")
+            .cloned()
+            .unwrap_or_default(),
+        description: matches.get_one::<String>("mr-description").unwrap().into(),
+        labels: many(matches, "mr-label"),
+        reviewers: many(matches, "mr-reviewer"),
+        assignees: many(matches, "mr-assignee"),
+    });
+
+    let changes = load_changes(matches, &mut file)?;
+    let provider = provider(matches, &mut file)?;
 
     let config = Task {
-        provider: serde_json::from_str(
-            matches
-                .get_one::<String>("provider")
-                .ok_or_else(|| anyhow!("Missing provider info (--provider or SHIPIT_PROVIDER)"))?
-                .as_str(),
-        )?,
-        changes: serde_json::from_str(
-            matches
-                .get_one::<String>("changeset")
-                .ok_or_else(|| anyhow!("Missing changeset (--changeset or SHIPIT_CHANGES)"))?
-                .as_str(),
-        )?,
-        branch: matches.get_one::<String>("branch").unwrap().into(),
-        author: matches.get_one::<String>("author").unwrap().into(),
-        message: matches.get_one::<String>("message").unwrap().into(),
+        changes,
+        // merge requests are opened from a separate branch, created from the target one
+        branch: merge_request
+            .as_ref()
+            .map_or(target_branch.clone(), |mr| mr.source_branch.clone()),
+        start_branch: match merge_request {
+            Some(_) => Some(target_branch),
+            None => matches
+                .get_one::<String>("start-branch")
+                .cloned()
+                .or(file.start_branch),
+        },
+        author,
+        committer: matches.get_one::<Identity>("committer").cloned(),
+        message,
+        trailers,
+        dry_run,
+        retries: *matches.get_one::<u32>("retries").unwrap(),
+        merge_request,
+        auto_merge: flag(matches, "auto-merge"),
+        wait: flag(matches, "wait").then(|| *matches.get_one::<Duration>("wait-timeout").unwrap()),
+        tag: optional::<String>(matches, "tag").map(|name| Tag {
+            name: name.clone(),
+            message: matches.get_one::<String>("tag-message").cloned(),
+        }),
+        release: optional::<String>(matches, "tag")
+            .filter(|_| flag(matches, "release"))
+            .map(|tag| Release {
+                tag: tag.clone(),
+                name: matches
+                    .get_one::<String>("release-name")
+                    .unwrap_or(tag)
+                    .clone(),
+                notes: matches
+                    .get_one::<String>("release-notes")
+                    .cloned()
+                    .unwrap_or_default(),
+            }),
+        status: optional::<String>(matches, "status")
+            .map(|state| -> Result<_> {
+                Ok(CommitStatus {
+                    state: state.parse()?,
+                    context: matches.get_one::<String>("status-context").unwrap().clone(),
+                    target_url: matches.get_one::<String>("status-url").cloned(),
+                    description: matches.get_one::<String>("status-description").cloned(),
+                })
+            })
+            .transpose()?,
+        status_sha: optional::<String>(matches, "status-sha").cloned(),
+        output: match matches.get_one::<String>("output").unwrap().as_str() {
+            "json" => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        },
+        output_file: optional::<PathBuf>(matches, "output-file").cloned(),
     };
 
-    log::debug!("using provider {}", config.provider.name());
+    log::debug!("using {} provider {:?}", provider.name(), provider);
 
-    let mut repo = get_repository(config.provider);
+    let mut repo = get_repository(provider)?;
+    run(&config, &mut *repo)
+}
 
+/// commits the changes, then opens the merge request, tags and reports as configured
+fn run(config: &Task, repo: &mut dyn Repository) -> Result<()> {
     log::debug!(
         "computing changes: {:?} to branch {}",
         config.changes,
         config.branch
     );
 
-    let changes = mutate(&*repo, &config.branch, &config.changes)?;
+    // a source branch without an open merge request is left over from an earlier one,
+    // it is recreated from the target branch rather than committed on top of
+    let mut stale = match &config.merge_request {
+        Some(merge_request) => {
+            repo.branch_exists(&config.branch)?
+                && repo
+                    .find_merge_request(&merge_request.source_branch, &merge_request.target_branch)?
+                    .is_none()
+        }
+        None => false,
+    };
+
+    let mut attempt = 0;
+    // the last rendered commit message, without trailers
+    let mut last_message = None;
+    let mut info = loop {
+        // a missing branch is created from the start branch, which the files are read from
+        let start_branch = match &config.start_branch {
+            Some(start_branch) if stale || !repo.branch_exists(&config.branch)? => {
+                log::debug!("creating branch {} from {start_branch}", config.branch);
+                Some(start_branch.clone())
+            }
+            _ => None,
+        };
+        let reference = start_branch.as_deref().unwrap_or(&config.branch);
+
+        let changes = mutate(&*repo, reference, &config.changes)?;
+
+        if changes.is_empty() {
+            log::info!("nothing to commit, branch {} is up to date", config.branch);
+            // the branch only exists if it wasn't going to be created
+            break start_branch.is_none().then(CommitInfo::default);
+        }
+
+        let mut commit = CommitRequest {
+            branch: config.branch.clone(),
+            start_branch,
+            author: config.author.clone(),
+            committer: config.committer.clone(),
+            message: String::new(),
+            files: changes.files,
+            deleted: changes.deleted,
+            moved: changes.moved,
+        };
+        let rendered = message::render(
+            &config.message,
+            &commit.branch,
+            &commit.paths(),
+            &changes.values,
+        )?;
+        commit.message = with_trailers(&rendered, &config.trailers);
+        last_message = Some(rendered);
 
-    let commit = CommitRequest {
-        branch: config.branch,
-        author: config.author,
-        message: config.message,
-        files: changes,
+        if config.dry_run {
+            let color = std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+            print!("{}", diff::render(&*repo, &commit, color)?);
+            return Ok(());
+        }
+
+        if stale {
+            log::warn!(
+                "branch {} has no open merge request, recreating it",
+                config.branch
+            );
+            repo.delete_branch(&config.branch)?;
+            stale = false;
+        }
+
+        match repo.commit(commit) {
+            // someone else committed in the meantime, re-apply the changes on top of their commit
+            Err(err) if err.is::<Conflict>() && attempt < config.retries => {
+                attempt += 1;
+                log::warn!("{err}, retrying ({attempt}/{})", config.retries);
+            }
+            result => break Some(result?),
+        }
     };
 
-    repo.commit(commit)?;
+    // a preview with nothing to commit stops before opening merge requests, tagging or reporting
+    if config.dry_run {
+        return Ok(());
+    }
+
+    // the commit the merge request landed as, which is what gets tagged
+    let mut merged_sha = None;
+    if let (Some(merge_request), Some(info)) = (&config.merge_request, &mut info) {
+        let mut merge_request = merge_request.clone();
+        if merge_request.title.is_empty() {
+            // nothing was committed on a rerun, so there are no changes to render the message with
+            let message = match last_message {
+                Some(message) => message,
+                None => message::render(&config.message, &config.branch, &[], &[])
+                    .unwrap_or_else(|_| config.message.clone()),
+            };
+            merge_request.title = message.lines().next().unwrap_or_default().into();
+        }
+        let opened = repo.open_merge_request(&merge_request)?;
+        if config.output == OutputFormat::Text {
+            println!("{}", opened.web_url);
+        }
+        info.merge_request_url = Some(opened.web_url.clone());
+
+        if config.auto_merge {
+            repo.enable_auto_merge(&opened)?;
+        }
+        if let Some(timeout) = config.wait {
+            log::info!("waiting for {} to be merged", opened.web_url);
+            merged_sha = Some(merge_request::wait(
+                &*repo,
+                &opened,
+                timeout,
+                WAIT_INTERVAL,
+            )?);
+        }
+    }
+
+    let mut info = info.unwrap_or_default();
+    // a rerun may have nothing to commit, but still wait for the merge request opened earlier
+    match (&config.tag, merged_sha.as_deref().unwrap_or(&info.sha)) {
+        (Some(_), "") => log::info!("nothing was committed, skipping tag"),
+        (Some(tag), sha) => {
+            repo.create_tag(tag, sha)?;
+            if let Some(release) = &config.release {
+                info.release_url = Some(repo.create_release(release)?);
+            }
+        }
+        (None, _) => {}
+    }
+
+    if let Some(status) = &config.status {
+        match config.status_sha.as_deref().unwrap_or(&info.sha) {
+            "" => log::info!("nothing was committed, skipping commit status"),
+            sha => repo.set_commit_status(sha, status)?,
+        }
+    }
+
+    if config.output == OutputFormat::Json {
+        println!("{}", serde_json::to_string(&info)?);
+    }
+    if let Some(path) = &config.output_file {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(info.to_env().as_bytes())?;
+    }
 
     log::info!("done!");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::repository::InMemoryRepository;
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let mut repo = InMemoryRepository::default();
+        repo.commit(CommitRequest {
+            branch: "main".into(),
+            files: HashMap::from([("values.yaml".into(), Bytes::from("tag: '1.0'\n"))]),
+            ..Default::default()
+        })
+        .unwrap();
+        let output_file =
+            std::env::temp_dir().join(format!("shipit-output-{}", std::process::id()));
+
+        // the in-memory repository fails on tags and commit statuses
+        let config = Task {
+            changes: vec![Mutation::Yaml {
+                file: "values.yaml".into(),
+                changes: HashMap::from([("tag".into(), "1.0".into())]),
+            }],
+            branch: "main".into(),
+            start_branch: None,
+            author: "shipit <shipit@localhost>".parse().unwrap(),
+            committer: None,
+            message: "Update deployment".into(),
+            trailers: vec![],
+            dry_run: true,
+            retries: 0,
+            merge_request: None,
+            auto_merge: false,
+            wait: None,
+            tag: Some(Tag {
+                name: "v1.0".into(),
+                message: None,
+            }),
+            release: None,
+            status: Some(CommitStatus {
+                state: "success".parse().unwrap(),
+                context: "shipit".into(),
+                target_url: None,
+                description: None,
+            }),
+            status_sha: Some("abc".into()),
+            output: OutputFormat::Text,
+            output_file: Some(output_file.clone()),
+        };
+
+        run(&config, &mut repo).unwrap();
+        assert!(!output_file.exists());
+    }
+}
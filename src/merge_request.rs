@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::repository::Repository;
+
+#[derive(Clone, Debug, Default)]
+pub struct MergeRequest {
+    pub source_branch: String,
+    pub target_branch: String,
+    pub title: String,
+    pub description: String,
+    pub labels: Vec<String>,
+    /// usernames of the reviewers
+    pub reviewers: Vec<String>,
+    /// usernames of the assignees
+    pub assignees: Vec<String>,
+}
+
+/// a merge request as opened on the provider
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeRequestInfo {
+    /// the project-scoped number of the merge request (IID on GitLab, index on Gitea)
+    pub number: u64,
+    pub web_url: String,
+}
+
+/// the state of a merge request as reported by the provider
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeRequestState {
+    Open,
+    /// with the SHA of the commit it was merged as
+    Merged(String),
+    Closed,
+    /// still open, but its pipeline or checks failed
+    ChecksFailed,
+}
+
+/// polls the merge request until it's merged, failing if it's closed, its checks fail or timeout elapses.
+/// Returns the SHA of the commit it was merged as
+pub fn wait(
+    repository: &dyn Repository,
+    merge_request: &MergeRequestInfo,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let state = repository.merge_request_state(merge_request)?;
+        log::debug!(
+            "merge request state={state:?} url={}",
+            merge_request.web_url
+        );
+        match state {
+            MergeRequestState::Merged(sha) => return Ok(sha),
+            MergeRequestState::Closed => {
+                return Err(anyhow!(
+                    "merge request {} was closed",
+                    merge_request.web_url
+                ))
+            }
+            MergeRequestState::ChecksFailed => {
+                return Err(anyhow!(
+                    "checks failed for merge request {}",
+                    merge_request.web_url
+                ))
+            }
+            MergeRequestState::Open if Instant::now() >= deadline => {
+                return Err(anyhow!(
+                    "timed out waiting for merge request {} to be merged",
+                    merge_request.web_url
+                ))
+            }
+            MergeRequestState::Open => std::thread::sleep(interval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repository::InMemoryRepository;
+
+    use super::*;
+
+    fn wait_for(states: &[MergeRequestState], timeout: Duration) -> Result<String> {
+        let repository = InMemoryRepository::default();
+        repository
+            .merge_request_states
+            .borrow_mut()
+            .extend(states.iter().cloned());
+        let merge_request = MergeRequestInfo {
+            number: 1,
+            web_url: "https://example.com/mr/1".into(),
+        };
+        let result = wait(
+            &repository,
+            &merge_request,
+            timeout,
+            Duration::from_millis(1),
+        );
+        assert!(repository.merge_request_states.borrow().is_empty());
+        result
+    }
+
+    #[test]
+    fn test_wait() {
+        use MergeRequestState::*;
+        let timeout = Duration::from_secs(10);
+
+        assert_eq!(
+            wait_for(&[Open, Open, Merged("abc".into())], timeout).unwrap(),
+            "abc"
+        );
+
+        let err = wait_for(&[Open, Closed], timeout).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "merge request https://example.com/mr/1 was closed"
+        );
+
+        let err = wait_for(&[ChecksFailed], timeout).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "checks failed for merge request https://example.com/mr/1"
+        );
+
+        let err = wait_for(&[Open], Duration::ZERO).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "timed out waiting for merge request https://example.com/mr/1 to be merged"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use minijinja::{Environment, UndefinedBehavior};
+use serde_json::json;
+
+use crate::commit::ValueChange;
+
+/// prefixes of the environment variables templates can read
+const ENV_PREFIXES: &[&str] = &["CI_", "GITHUB_", "GITEA_", "SHIPIT_"];
+/// parts of the names of variables which may hold credentials
+const ENV_DENIED: &[&str] = &[
+    "TOKEN",
+    "PASSWORD",
+    "SECRET",
+    "KEY",
+    "JWT",
+    "CI_REPOSITORY_URL",
+    "SHIPIT_PROVIDER",
+];
+
+/// renders a commit message template with the changes being committed and the environment.
+/// Templates only see the CI metadata and `SHIPIT_*` variables, without credentials
+pub fn render(
+    template: &str,
+    branch: &str,
+    files: &[String],
+    values: &[ValueChange],
+) -> Result<String> {
+    let env = std::env::vars().filter(|(name, _)| exposed(name)).collect();
+    render_with_env(template, branch, files, values, env)
+}
+
+fn exposed(name: &str) -> bool {
+    (name == "CI" || ENV_PREFIXES.iter().any(|prefix| name.starts_with(prefix)))
+        && !ENV_DENIED.iter().any(|denied| name.contains(denied))
+}
+
+fn render_with_env(
+    template: &str,
+    branch: &str,
+    files: &[String],
+    values: &[ValueChange],
+    env: HashMap<String, String>,
+) -> Result<String> {
+    let mut environment = Environment::new();
+    environment.set_undefined_behavior(UndefinedBehavior::Strict);
+
+    environment
+        .render_str(
+            template,
+            json!({
+                "branch": branch,
+                "files": files,
+                "changes": values,
+                "env": env,
+            }),
+        )
+        .map_err(|err| {
+            anyhow!(
+                "could not render commit message (wrap literal braces in {{% raw %}}...{{% endraw %}}): {:#}",
+                err
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let values = [ValueChange {
+            file: "prod/values.yaml".into(),
+            key: "image.tag".into(),
+            old: Some("1.4.2".into()),
+            new: "1.5.0".into(),
+        }];
+
+        assert_eq!(
+            render_with_env(
+                "chore({{ env.SHIPIT_ENVIRONMENT }}): bump api image {{ changes[0].old }} -> {{ changes[0].new }}\n\n\
+                 {% for file in files %}- {{ file }} on {{ branch }}{% endfor %}",
+                "main",
+                &["prod/values.yaml".into()],
+                &values,
+                HashMap::from([("SHIPIT_ENVIRONMENT".into(), "prod".into())]),
+            )
+            .unwrap(),
+            "chore(prod): bump api image 1.4.2 -> 1.5.0\n\n- prod/values.yaml on main"
+        );
+
+        assert_eq!(
+            render_with_env("Update deployment", "main", &[], &[], HashMap::new()).unwrap(),
+            "Update deployment"
+        );
+        assert!(render_with_env("{{ missing }}", "main", &[], &[], HashMap::new()).is_err());
+        assert_eq!(
+            render_with_env(
+                "{% raw %}{{ literal }}{% endraw %}",
+                "main",
+                &[],
+                &[],
+                HashMap::new()
+            )
+            .unwrap(),
+            "{{ literal }}"
+        );
+    }
+
+    #[test]
+    fn test_exposed_env() {
+        for name in [
+            "CI",
+            "CI_COMMIT_SHA",
+            "GITHUB_SHA",
+            "GITEA_ACTIONS",
+            "SHIPIT_ENVIRONMENT",
+        ] {
+            assert!(exposed(name), "{name}");
+        }
+        for name in [
+            "HOME",
+            "AWS_SECRET_ACCESS_KEY",
+            "CI_JOB_TOKEN",
+            "CI_REGISTRY_PASSWORD",
+            "CI_JOB_JWT",
+            "CI_JOB_JWT_V1",
+            "CI_JOB_JWT_V2",
+            "CI_REPOSITORY_URL",
+            "GITHUB_TOKEN",
+            "SHIPIT_PROVIDER",
+            "SHIPIT_DEPLOY_KEY",
+        ] {
+            assert!(!exposed(name), "{name}");
+        }
+    }
+}
@@ -1,44 +1,160 @@
+use super::token::Token;
 use crate::{
-    commit::{CommitRequest, FileList},
-    repository::Repository,
+    commit::{CommitInfo, CommitRequest, FileList},
+    merge_request::{MergeRequest, MergeRequestInfo, MergeRequestState},
+    repository::{Conflict, Repository},
+    status::{CommitStatus, State},
+    tag::{Release, Tag},
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use base64::{engine::general_purpose, Engine};
 use bytes::Bytes;
 use reqwest::{
-    blocking::Client,
-    header::{AUTHORIZATION, CONTENT_TYPE},
+    blocking::{Client, Response},
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    StatusCode,
 };
 use serde::Deserialize;
 use serde_json::json;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 #[derive(Deserialize)]
 struct GiteaFileInfo {
     sha: String,
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GiteaBranch {
+    #[serde(rename = "ref")]
+    reference: String,
+    #[serde(default)]
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaCommit {
+    sha: String,
+    html_url: String,
+}
+
+/// returned when changing a file through the contents API
+#[derive(Deserialize)]
+struct GiteaFileResponse {
+    commit: GiteaCommit,
+}
+
+#[derive(Deserialize)]
+struct GiteaPullRequestState {
+    state: String,
+    merged: bool,
+    merge_commit_sha: Option<String>,
+    head: GiteaBranch,
+}
+
+#[derive(Deserialize)]
+struct GiteaCombinedStatus {
+    state: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaPullRequest {
+    number: u64,
+    html_url: String,
+    head: GiteaBranch,
+    base: GiteaBranch,
+}
+
+#[derive(Deserialize)]
+struct GiteaRelease {
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaLabel {
+    id: u64,
+    name: String,
+}
+
+/// the page size used when listing resources
+const PAGE_LIMIT: usize = 50;
+
+/// how the credentials are sent to Gitea
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Auth {
+    /// HTTP Basic, credentials are `user:password` or `user:token`
+    #[default]
+    Basic,
+    /// an access token, sent as `token <PAT>`
+    Token,
+    /// an OAuth2 access token
+    Bearer,
+}
+
+impl FromStr for Auth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "basic" => Ok(Auth::Basic),
+            "token" => Ok(Auth::Token),
+            "bearer" => Ok(Auth::Bearer),
+            _ => bail!("invalid Gitea auth {}, expected basic, token or bearer", s),
+        }
+    }
 }
 
 pub struct Gitea {
     api_url: String,
     project_id: String,
-    credentials: String,
+    credentials: Token,
+    auth: Auth,
     client: Client,
+    /// blob SHAs of the files as they were read, to detect concurrent changes on commit
+    shas: RefCell<HashMap<String, String>>,
 }
 
 impl Gitea {
-    pub fn new(api_url: String, project_id: String, credentials: String) -> Self {
+    pub fn new(api_url: String, project_id: String, credentials: Token) -> Self {
         Self {
             api_url,
             project_id,
             credentials,
+            auth: Auth::default(),
             client: Client::new(),
+            shas: RefCell::default(),
         }
     }
 
-    fn auth_header(&self) -> String {
-        format!(
-            "Basic {}",
-            general_purpose::STANDARD.encode(&self.credentials)
-        )
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn auth_header(&self) -> Result<HeaderValue> {
+        let credentials = self.credentials.get()?;
+        let value = match self.auth {
+            Auth::Basic => format!("Basic {}", general_purpose::STANDARD.encode(credentials)),
+            Auth::Token => format!("token {credentials}"),
+            Auth::Bearer => format!("Bearer {credentials}"),
+        };
+        let mut value = HeaderValue::from_str(&value)?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+
+    /// returns the SHA recorded when path was read, or its current one
+    fn known_sha(&self, path: &str, reference: &str) -> Result<String> {
+        match self.shas.borrow().get(path) {
+            Some(sha) => Ok(sha.clone()),
+            None => self.file_sha(path, reference),
+        }
     }
 
     fn file_sha(&self, path: &str, reference: &str) -> Result<String> {
@@ -49,7 +165,7 @@ impl Gitea {
                 self.api_url, self.project_id, path
             ))
             .query(&[("ref", reference)])
-            .header(AUTHORIZATION, self.auth_header())
+            .header(AUTHORIZATION, self.auth_header()?)
             .send()?;
 
         if response.status() == 404 {
@@ -63,9 +179,77 @@ impl Gitea {
         Ok(response.json::<GiteaFileInfo>()?.sha)
     }
 
-    fn commit_file(&self, payload: CommitRequest) -> Result<()> {
-        let (author, email) = payload.split_author();
+    /// lists every item of a paginated collection
+    fn list<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<T>> {
+        let mut items = vec![];
+        for page in 1.. {
+            let response = self
+                .client
+                .get(format!(
+                    "{}/repos/{}/{}",
+                    self.api_url, self.project_id, path
+                ))
+                .query(query)
+                .query(&[
+                    ("page", page.to_string()),
+                    ("limit", PAGE_LIMIT.to_string()),
+                ])
+                .header(AUTHORIZATION, self.auth_header()?)
+                .send()?;
+            if !response.status().is_success() {
+                return Err(anyhow!(response.text()?));
+            }
+
+            let page: Vec<T> = response.json()?;
+            let last = page.len() < PAGE_LIMIT;
+            items.extend(page);
+            if last {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Gitea references labels by ID when creating pull requests
+    fn label_ids(&self, names: &[String]) -> Result<Vec<u64>> {
+        if names.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let labels: Vec<GiteaLabel> = self.list("labels", &[])?;
+        names
+            .iter()
+            .map(|name| {
+                labels
+                    .iter()
+                    .find(|label| &label.name == name)
+                    .map(|label| label.id)
+                    .ok_or_else(|| anyhow!("label {} not found", name))
+            })
+            .collect()
+    }
+
+    fn raw(&self, path: &str, reference: &str) -> Result<Bytes> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/repos/{}/raw/{}",
+                self.api_url, self.project_id, path
+            ))
+            .query(&[("ref", reference)])
+            .header(AUTHORIZATION, self.auth_header()?)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        Ok(response.bytes()?)
+    }
 
+    fn commit_file(&self, payload: CommitRequest) -> Result<GiteaCommit> {
         // Our payload should contain exactly one file
         let (file, content) = payload
             .files
@@ -74,20 +258,32 @@ impl Gitea {
             .next()
             .ok_or_else(|| anyhow!("No file found"))?;
 
-        // if the original file already exists we need to provide their latest SHA
-        let sha = self.file_sha(file, &payload.branch)?;
+        // a moved file replaces the one at its previous path
+        let from_path = payload.moved.get(file);
+
+        // when creating the branch, the file is read from the one it starts from
+        let reference = payload.start_branch.as_deref().unwrap_or(&payload.branch);
 
-        let body = json!({
-            "author": {
-              "name": author,
-              "email": email
-            },
+        // if the original file already exists we need to provide the SHA it had when it was read
+        let sha = self.known_sha(from_path.unwrap_or(file), reference)?;
+
+        let mut body = json!({
+            "author": payload.author,
             "message": payload.message,
-            "branch": payload.branch,
+            "branch": reference,
             "content": general_purpose::STANDARD.encode(content),
             "sha": sha,
-        })
-        .to_string();
+        });
+        if let Some(from_path) = from_path {
+            body["from_path"] = from_path.as_str().into();
+        }
+        if payload.start_branch.is_some() {
+            body["new_branch"] = payload.branch.as_str().into();
+        }
+        if let Some(committer) = &payload.committer {
+            body["committer"] = json!(committer);
+        }
+        let body = body.to_string();
 
         let response = self
             .client
@@ -95,20 +291,96 @@ impl Gitea {
                 "{}/repos/{}/contents/{}",
                 self.api_url, self.project_id, file
             ))
-            .header(AUTHORIZATION, self.auth_header())
+            .header(AUTHORIZATION, self.auth_header()?)
             .header(CONTENT_TYPE, "application/json")
             .body(body)
             .send()?;
 
-        if !response.status().is_success() {
-            return Err(anyhow!(response.text()?));
+        let GiteaFileResponse { commit } = check_conflict(response)?.json()?;
+        Ok(commit)
+    }
+
+    fn delete_file(&self, payload: CommitRequest) -> Result<GiteaCommit> {
+        // Our payload should contain exactly one file
+        let file = payload
+            .deleted
+            .iter()
+            .next()
+            .ok_or_else(|| anyhow!("No file found"))?;
+
+        let reference = payload.start_branch.as_deref().unwrap_or(&payload.branch);
+        let sha = self.known_sha(file, reference)?;
+        if sha.is_empty() {
+            return Err(anyhow!("File {} not found", file));
         }
 
-        Ok(())
+        let mut body = json!({
+            "author": payload.author,
+            "message": payload.message,
+            "branch": reference,
+            "sha": sha,
+        });
+        if payload.start_branch.is_some() {
+            body["new_branch"] = payload.branch.as_str().into();
+        }
+        if let Some(committer) = &payload.committer {
+            body["committer"] = json!(committer);
+        }
+        let body = body.to_string();
+
+        let response = self
+            .client
+            .delete(format!(
+                "{}/repos/{}/contents/{}",
+                self.api_url, self.project_id, file
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()?;
+
+        let GiteaFileResponse { commit } = check_conflict(response)?.json()?;
+        Ok(commit)
     }
 }
 
+/// maps the errors Gitea returns when a file changed since it was read to a Conflict
+fn check_conflict(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let message = response.text()?;
+    if status == StatusCode::CONFLICT
+        || (status == StatusCode::UNPROCESSABLE_ENTITY
+            && (message.contains("sha does not match") || message.contains("already exists")))
+    {
+        return Err(Conflict(message).into());
+    }
+    Err(anyhow!(message))
+}
+
 impl Repository for Gitea {
+    fn branch_exists(&self, branch: &str) -> Result<bool> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/repos/{}/branches/{}",
+                self.api_url, self.project_id, branch
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .send()?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        Ok(true)
+    }
+
     fn get(&self, path: &str, reference: &str) -> Result<Bytes> {
         log::debug!(
             "fetching file path={path} project={} ref={reference} api_url={}",
@@ -119,19 +391,30 @@ impl Repository for Gitea {
         let response = self
             .client
             .get(format!(
-                "{}/repos/{}/raw/{}",
+                "{}/repos/{}/contents/{}",
                 self.api_url, self.project_id, path
             ))
             .query(&[("ref", reference)])
-            .header(AUTHORIZATION, self.auth_header())
+            .header(AUTHORIZATION, self.auth_header()?)
             .send()?;
         if !response.status().is_success() {
             return Err(anyhow!(response.text()?));
         }
-        Ok(response.bytes()?)
+
+        let GiteaFileInfo { sha, content } = response.json()?;
+        let content = match content {
+            Some(content) => {
+                Bytes::from(general_purpose::STANDARD.decode(content.replace('\n', ""))?)
+            }
+            // Gitea omits the content of large files
+            None => self.raw(path, reference)?,
+        };
+
+        self.shas.borrow_mut().insert(path.into(), sha);
+        Ok(content)
     }
 
-    fn commit(&mut self, payload: CommitRequest) -> Result<()> {
+    fn commit(&mut self, payload: CommitRequest) -> Result<CommitInfo> {
         log::debug!(
             "committing changes author={} ref={} message={} project={} api_url={}",
             payload.author,
@@ -141,34 +424,382 @@ impl Repository for Gitea {
             self.api_url
         );
 
-        let multiple_files = payload.files.len() > 1;
-        for file in payload.files {
-            self.commit_file(CommitRequest {
+        // Gitea creates one commit per file
+        let moved_only = payload
+            .moved
+            .keys()
+            .filter(|file| !payload.files.contains_key(*file))
+            .count();
+        let multiple_files = payload.files.len() + payload.deleted.len() + moved_only > 1;
+        let message = |file: &str| {
+            if multiple_files {
+                format!("{}: {}", file, payload.message.clone())
+            } else {
+                payload.message.clone()
+            }
+        };
+
+        let reference = payload.start_branch.as_deref().unwrap_or(&payload.branch);
+        let mut commits = vec![];
+        for (file, from) in &payload.moved {
+            // renaming requires the content of the file, even if unchanged
+            let content = match payload.files.get(file) {
+                Some(content) => content.clone(),
+                None => self.get(from, reference)?,
+            };
+            commits.push(CommitRequest {
                 branch: payload.branch.clone(),
                 author: payload.author.clone(),
-                message: if multiple_files {
-                    format!("{}: {}", file.0, payload.message.clone())
-                } else {
-                    payload.message.clone()
-                },
-                files: FileList::from([file]),
-            })?;
+                committer: payload.committer.clone(),
+                message: message(file),
+                files: FileList::from([(file.clone(), content)]),
+                moved: HashMap::from([(file.clone(), from.clone())]),
+                ..Default::default()
+            });
+        }
+        for (file, content) in &payload.files {
+            if payload.moved.contains_key(file) {
+                continue;
+            }
+            commits.push(CommitRequest {
+                branch: payload.branch.clone(),
+                author: payload.author.clone(),
+                committer: payload.committer.clone(),
+                message: message(file),
+                files: FileList::from([(file.clone(), content.clone())]),
+                ..Default::default()
+            });
+        }
+        for file in &payload.deleted {
+            commits.push(CommitRequest {
+                branch: payload.branch.clone(),
+                author: payload.author.clone(),
+                committer: payload.committer.clone(),
+                message: message(file),
+                deleted: HashSet::from([file.clone()]),
+                ..Default::default()
+            });
+        }
+
+        // only the first commit creates the branch, the others are made on top of it
+        let mut start_branch = payload.start_branch.clone();
+        let mut last = None;
+        for mut commit in commits {
+            commit.start_branch = start_branch.take();
+            last = Some(if commit.deleted.is_empty() {
+                self.commit_file(commit)?
+            } else {
+                self.delete_file(commit)?
+            });
+        }
+        let GiteaCommit { sha, html_url } = last.ok_or_else(|| anyhow!("No file found"))?;
+
+        self.shas.borrow_mut().clear();
+        log::info!("commit URL: {html_url}");
+
+        Ok(CommitInfo {
+            sha,
+            web_url: html_url,
+            files: payload.paths(),
+            merge_request_url: None,
+            release_url: None,
+        })
+    }
+
+    fn delete_branch(&mut self, branch: &str) -> Result<()> {
+        log::debug!(
+            "deleting branch {branch} project={} api_url={}",
+            self.project_id,
+            self.api_url
+        );
+
+        let response = self
+            .client
+            .delete(format!(
+                "{}/repos/{}/branches/{}",
+                self.api_url, self.project_id, branch
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        Ok(())
+    }
+
+    fn find_merge_request(
+        &self,
+        source_branch: &str,
+        target_branch: &str,
+    ) -> Result<Option<MergeRequestInfo>> {
+        Ok(self
+            .list::<GiteaPullRequest>("pulls", &[("state", "open")])?
+            .into_iter()
+            .find(|pull| {
+                pull.head.reference == source_branch && pull.base.reference == target_branch
+            })
+            .map(|pull| MergeRequestInfo {
+                number: pull.number,
+                web_url: pull.html_url,
+            }))
+    }
+
+    fn open_merge_request(&self, request: &MergeRequest) -> Result<MergeRequestInfo> {
+        if let Some(existing) =
+            self.find_merge_request(&request.source_branch, &request.target_branch)?
+        {
+            log::info!("pull request already open: {}", existing.web_url);
+            return Ok(existing);
+        }
+
+        log::debug!(
+            "opening pull request source={} target={} project={} api_url={}",
+            request.source_branch,
+            request.target_branch,
+            self.project_id,
+            self.api_url
+        );
+
+        let mut body = json!({
+            "head": request.source_branch,
+            "base": request.target_branch,
+            "title": request.title,
+            "body": request.description,
+        });
+        if !request.assignees.is_empty() {
+            body["assignees"] = request.assignees.clone().into();
+        }
+        if !request.labels.is_empty() {
+            body["labels"] = self.label_ids(&request.labels)?.into();
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/repos/{}/pulls", self.api_url, self.project_id))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        let GiteaPullRequest {
+            number, html_url, ..
+        } = response.json()?;
+
+        if !request.reviewers.is_empty() {
+            let response = self
+                .client
+                .post(format!(
+                    "{}/repos/{}/pulls/{}/requested_reviewers",
+                    self.api_url, self.project_id, number
+                ))
+                .header(AUTHORIZATION, self.auth_header()?)
+                .header(CONTENT_TYPE, "application/json")
+                .body(json!({ "reviewers": request.reviewers }).to_string())
+                .send()?;
+            if !response.status().is_success() {
+                return Err(anyhow!(response.text()?));
+            }
+        }
+
+        log::info!("pull request URL: {html_url}");
+
+        Ok(MergeRequestInfo {
+            number,
+            web_url: html_url,
+        })
+    }
+
+    fn enable_auto_merge(&self, merge_request: &MergeRequestInfo) -> Result<()> {
+        log::debug!(
+            "enabling auto-merge index={} project={} api_url={}",
+            merge_request.number,
+            self.project_id,
+            self.api_url
+        );
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/repos/{}/pulls/{}/merge",
+                self.api_url, self.project_id, merge_request.number
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({ "Do": "merge", "merge_when_checks_succeed": true }).to_string())
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+
+        Ok(())
+    }
+
+    fn merge_request_state(&self, merge_request: &MergeRequestInfo) -> Result<MergeRequestState> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/repos/{}/pulls/{}",
+                self.api_url, self.project_id, merge_request.number
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        let GiteaPullRequestState {
+            state,
+            merged,
+            merge_commit_sha,
+            head,
+        } = response.json()?;
+
+        if merged {
+            return Ok(MergeRequestState::Merged(
+                merge_commit_sha.unwrap_or(head.sha),
+            ));
+        }
+        if state == "closed" {
+            return Ok(MergeRequestState::Closed);
+        }
+
+        let response = self
+            .client
+            .get(format!(
+                "{}/repos/{}/commits/{}/status",
+                self.api_url, self.project_id, head.sha
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        let GiteaCombinedStatus { state } = response.json()?;
+
+        Ok(match state.as_str() {
+            "failure" | "error" => MergeRequestState::ChecksFailed,
+            _ => MergeRequestState::Open,
+        })
+    }
+
+    fn create_tag(&self, tag: &Tag, sha: &str) -> Result<()> {
+        log::debug!(
+            "creating tag name={} sha={sha} project={} api_url={}",
+            tag.name,
+            self.project_id,
+            self.api_url
+        );
+
+        // Gitea creates an annotated tag when given a message
+        let response = self
+            .client
+            .post(format!("{}/repos/{}/tags", self.api_url, self.project_id))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                json!({
+                    "tag_name": tag.name,
+                    "target": sha,
+                    "message": tag.message.as_deref().unwrap_or_default(),
+                })
+                .to_string(),
+            )
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
         }
+
+        Ok(())
+    }
+
+    fn create_release(&self, release: &Release) -> Result<String> {
+        log::debug!(
+            "creating release tag={} project={} api_url={}",
+            release.tag,
+            self.project_id,
+            self.api_url
+        );
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/repos/{}/releases",
+                self.api_url, self.project_id
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                json!({
+                    "tag_name": release.tag,
+                    "name": release.name,
+                    "body": release.notes,
+                })
+                .to_string(),
+            )
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+        let GiteaRelease { html_url } = response.json()?;
+
+        log::info!("release URL: {html_url}");
+
+        Ok(html_url)
+    }
+
+    fn set_commit_status(&self, sha: &str, status: &CommitStatus) -> Result<()> {
+        log::debug!(
+            "setting commit status sha={sha} state={:?} context={} project={} api_url={}",
+            status.state,
+            status.context,
+            self.project_id,
+            self.api_url
+        );
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/repos/{}/statuses/{}",
+                self.api_url, self.project_id, sha
+            ))
+            .header(AUTHORIZATION, self.auth_header()?)
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                json!({
+                    "state": match status.state {
+                        State::Pending => "pending",
+                        State::Success => "success",
+                        State::Failure => "failure",
+                    },
+                    "context": status.context,
+                    "target_url": status.target_url.as_deref().unwrap_or_default(),
+                    "description": status.description.as_deref().unwrap_or_default(),
+                })
+                .to_string(),
+            )
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(response.text()?));
+        }
+
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use mockito::Matcher;
+
     use super::*;
 
     #[test]
     fn test_get() {
         let mut server = mockito::Server::new();
         let get_mock = server
-            .mock("GET", "/repos/test/raw/test?ref=master")
+            .mock("GET", "/repos/test/contents/test?ref=master")
             .match_header("authorization", "Basic dGVzdA==")
-            .with_body("hello!")
+            .with_body(r#"{"content":"aGVsbG8h","sha":"test"}"#)
             .create();
 
         assert_eq!(
@@ -181,6 +812,32 @@ mod tests {
         get_mock.assert();
     }
 
+    #[test]
+    fn test_auth_header() {
+        let mut server = mockito::Server::new();
+
+        for (auth, header) in [
+            (Auth::Basic, "Basic c2VjcmV0"),
+            (Auth::Token, "token secret"),
+            (Auth::Bearer, "Bearer secret"),
+        ] {
+            let get_mock = server
+                .mock("GET", "/repos/test/contents/test?ref=master")
+                .match_header("authorization", header)
+                .with_body(r#"{"content":"aGVsbG8h","sha":"test"}"#)
+                .create();
+
+            let gitea = Gitea::new(server.url(), "test".into(), "secret".into()).with_auth(auth);
+            let value = gitea.auth_header().unwrap();
+            assert!(value.is_sensitive());
+            assert!(!format!("{:?}", value).contains("secret"));
+            gitea.get("test", "master").unwrap();
+
+            get_mock.assert();
+            get_mock.remove();
+        }
+    }
+
     #[test]
     fn test_commit_new() {
         let mut server = mockito::Server::new();
@@ -194,20 +851,32 @@ mod tests {
             .mock("PUT", "/repos/test/contents/test")
             .match_header("authorization", "Basic dGVzdA==")
             .match_header("content-type", "application/json")
-            .match_body(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","content":"dGVzdA==","message":"test","sha":""}"#)
+            .match_body(Matcher::JsonString(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","content":"dGVzdA==","message":"test","sha":""}"#.into()))
             .with_header("content-type", "application/json")
-            .with_body(r#"{}"#)
+            .with_body(r#"{"commit":{"sha":"abc","html_url":"https://example.com/commit/abc"}}"#)
             .create();
 
-        Gitea::new(server.url(), "test".into(), "test".into())
+        let info = Gitea::new(server.url(), "test".into(), "test".into())
             .commit(CommitRequest {
                 branch: "master".into(),
-                author: "test <author@email.tld>".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
                 message: "test".into(),
                 files: FileList::from([("test".into(), "test".into())]),
+                ..Default::default()
             })
             .unwrap();
 
+        assert_eq!(
+            info,
+            CommitInfo {
+                sha: "abc".into(),
+                web_url: "https://example.com/commit/abc".into(),
+                files: vec!["test".into()],
+                merge_request_url: None,
+                release_url: None,
+            }
+        );
+
         get_mock.assert();
         put_mock.assert();
     }
@@ -225,21 +894,358 @@ mod tests {
             .mock("PUT", "/repos/test/contents/test")
             .match_header("authorization", "Basic dGVzdA==")
             .match_header("content-type", "application/json")
-            .match_body(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","content":"dGVzdA==","message":"test","sha":"test"}"#)
+            .match_body(Matcher::JsonString(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","content":"dGVzdA==","message":"test","sha":"test"}"#.into()))
             .with_header("content-type", "application/json")
-            .with_body(r#"{}"#)
+            .with_body(r#"{"commit":{"sha":"abc","html_url":"https://example.com/commit/abc"}}"#)
             .create();
 
         Gitea::new(server.url(), "test".into(), "test".into())
             .commit(CommitRequest {
                 branch: "master".into(),
-                author: "test <author@email.tld>".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
                 message: "test".into(),
                 files: FileList::from([("test".into(), "test".into())]),
+                ..Default::default()
             })
             .unwrap();
 
         get_mock.assert();
         put_mock.assert();
     }
+
+    #[test]
+    fn test_commit_move_delete() {
+        let mut server = mockito::Server::new();
+
+        let get_moved_mock = server
+            .mock("GET", "/repos/test/contents/old?ref=master")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_body(r#"{"content":"dGVzdA==","sha":"old"}"#)
+            .expect(1)
+            .create();
+        let put_mock = server
+            .mock("PUT", "/repos/test/contents/new")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_header("content-type", "application/json")
+            .match_body(Matcher::JsonString(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","content":"dGVzdA==","from_path":"old","message":"new: test","sha":"old"}"#.into()))
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"commit":{"sha":"abc","html_url":"https://example.com/commit/abc"}}"#)
+            .create();
+        let get_deleted_mock = server
+            .mock("GET", "/repos/test/contents/deleted?ref=master")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_body(r#"{"content":"dGVzdA==","sha":"deleted"}"#)
+            .create();
+        let delete_mock = server
+            .mock("DELETE", "/repos/test/contents/deleted")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_header("content-type", "application/json")
+            .match_body(Matcher::JsonString(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","message":"deleted: test","sha":"deleted"}"#.into()))
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"commit":{"sha":"abc","html_url":"https://example.com/commit/abc"}}"#)
+            .create();
+
+        Gitea::new(server.url(), "test".into(), "test".into())
+            .commit(CommitRequest {
+                branch: "master".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                moved: HashMap::from([("new".into(), "old".into())]),
+                deleted: HashSet::from(["deleted".into()]),
+                ..Default::default()
+            })
+            .unwrap();
+
+        get_moved_mock.assert();
+        put_mock.assert();
+        get_deleted_mock.assert();
+        delete_mock.assert();
+    }
+
+    #[test]
+    fn test_commit_conflict() {
+        let mut server = mockito::Server::new();
+
+        let get_mock = server
+            .mock("GET", "/repos/test/contents/test?ref=master")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_body(r#"{"content":"dGVzdA==","sha":"read"}"#)
+            .expect(1)
+            .create();
+        let put_mock = server
+            .mock("PUT", "/repos/test/contents/test")
+            .match_body(Matcher::PartialJsonString(r#"{"sha":"read"}"#.into()))
+            .with_status(422)
+            .with_body(r#"{"message":"sha does not match [given: read, expected: newer]"}"#)
+            .create();
+
+        let mut gitea = Gitea::new(server.url(), "test".into(), "test".into());
+        gitea.get("test", "master").unwrap();
+        let err = gitea
+            .commit(CommitRequest {
+                branch: "master".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                files: FileList::from([("test".into(), "changed".into())]),
+                ..Default::default()
+            })
+            .unwrap_err();
+
+        assert!(err.is::<Conflict>());
+        get_mock.assert();
+        put_mock.assert();
+    }
+
+    #[test]
+    fn test_commit_new_branch() {
+        let mut server = mockito::Server::new();
+
+        let branch_mock = server
+            .mock("GET", "/repos/test/branches/feature")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_status(404)
+            .create();
+        let get_mock = server
+            .mock("GET", "/repos/test/contents/test?ref=master")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_status(404)
+            .create();
+        let put_mock = server
+            .mock("PUT", "/repos/test/contents/test")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(r#"{"author":{"email":"author@email.tld","name":"test"},"branch":"master","committer":{"email":"bot@email.tld","name":"bot"},"content":"dGVzdA==","message":"test","new_branch":"feature","sha":""}"#.into()))
+            .with_body(r#"{"commit":{"sha":"abc","html_url":"https://example.com/commit/abc"}}"#)
+            .create();
+
+        let mut gitea = Gitea::new(server.url(), "test".into(), "test".into());
+        assert!(!gitea.branch_exists("feature").unwrap());
+        gitea
+            .commit(CommitRequest {
+                branch: "feature".into(),
+                start_branch: Some("master".into()),
+                author: "test <author@email.tld>".parse().unwrap(),
+                committer: Some("bot <bot@email.tld>".parse().unwrap()),
+                message: "test".into(),
+                files: FileList::from([("test".into(), "test".into())]),
+                ..Default::default()
+            })
+            .unwrap();
+
+        branch_mock.assert();
+        get_mock.assert();
+        put_mock.assert();
+    }
+
+    #[test]
+    fn test_delete_branch() {
+        let mut server = mockito::Server::new();
+        let delete_mock = server
+            .mock("DELETE", "/repos/test/branches/shipit/master")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_status(204)
+            .create();
+
+        Gitea::new(server.url(), "test".into(), "test".into())
+            .delete_branch("shipit/master")
+            .unwrap();
+
+        delete_mock.assert();
+    }
+
+    #[test]
+    fn test_open_merge_request() {
+        let mut server = mockito::Server::new();
+
+        let list_mock = server
+            .mock("GET", "/repos/test/pulls?state=open&page=1&limit=50")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_body(r#"[{"number":1,"html_url":"https://example.com/pulls/1","head":{"ref":"other"},"base":{"ref":"master"}}]"#)
+            .create();
+        let labels_mock = server
+            .mock("GET", "/repos/test/labels?page=1&limit=50")
+            .match_header("authorization", "Basic dGVzdA==")
+            .with_body(r#"[{"id":3,"name":"deploy"}]"#)
+            .create();
+        let create_mock = server
+            .mock("POST", "/repos/test/pulls")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(r#"{"assignees":["assignee"],"base":"master","body":"","head":"shipit/master","labels":[3],"title":"test"}"#.into()))
+            .with_body(r#"{"number":2,"html_url":"https://example.com/pulls/2","head":{"ref":"shipit/master"},"base":{"ref":"master"}}"#)
+            .create();
+        let reviewers_mock = server
+            .mock("POST", "/repos/test/pulls/2/requested_reviewers")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(r#"{"reviewers":["reviewer"]}"#.into()))
+            .with_status(201)
+            .with_body("[]")
+            .create();
+
+        assert_eq!(
+            Gitea::new(server.url(), "test".into(), "test".into())
+                .open_merge_request(&MergeRequest {
+                    source_branch: "shipit/master".into(),
+                    target_branch: "master".into(),
+                    title: "test".into(),
+                    labels: vec!["deploy".into()],
+                    reviewers: vec!["reviewer".into()],
+                    assignees: vec!["assignee".into()],
+                    ..Default::default()
+                })
+                .unwrap(),
+            MergeRequestInfo {
+                number: 2,
+                web_url: "https://example.com/pulls/2".into()
+            }
+        );
+
+        list_mock.assert();
+        labels_mock.assert();
+        create_mock.assert();
+        reviewers_mock.assert();
+    }
+
+    #[test]
+    fn test_auto_merge() {
+        let mut server = mockito::Server::new();
+        let merge_request = MergeRequestInfo {
+            number: 2,
+            web_url: "https://example.com/pulls/2".into(),
+        };
+
+        let merge_mock = server
+            .mock("POST", "/repos/test/pulls/2/merge")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(
+                r#"{"Do":"merge","merge_when_checks_succeed":true}"#.into(),
+            ))
+            .create();
+
+        Gitea::new(server.url(), "test".into(), "test".into())
+            .enable_auto_merge(&merge_request)
+            .unwrap();
+
+        merge_mock.assert();
+
+        for (pull, status, state) in [
+            (
+                r#"{"state":"open","merged":false,"head":{"ref":"shipit/master","sha":"abc"}}"#,
+                Some("pending"),
+                MergeRequestState::Open,
+            ),
+            (
+                r#"{"state":"open","merged":false,"head":{"ref":"shipit/master","sha":"abc"}}"#,
+                Some("failure"),
+                MergeRequestState::ChecksFailed,
+            ),
+            (
+                r#"{"state":"closed","merged":true,"merge_commit_sha":"def","head":{"ref":"shipit/master","sha":"abc"}}"#,
+                None,
+                MergeRequestState::Merged("def".into()),
+            ),
+            (
+                r#"{"state":"closed","merged":false,"head":{"ref":"shipit/master","sha":"abc"}}"#,
+                None,
+                MergeRequestState::Closed,
+            ),
+        ] {
+            let pull_mock = server
+                .mock("GET", "/repos/test/pulls/2")
+                .match_header("authorization", "Basic dGVzdA==")
+                .with_body(pull)
+                .create();
+            let status_mock = server
+                .mock("GET", "/repos/test/commits/abc/status")
+                .match_header("authorization", "Basic dGVzdA==")
+                .with_body(format!(r#"{{"state":"{}"}}"#, status.unwrap_or_default()))
+                .expect(usize::from(status.is_some()))
+                .create();
+
+            assert_eq!(
+                Gitea::new(server.url(), "test".into(), "test".into())
+                    .merge_request_state(&merge_request)
+                    .unwrap(),
+                state
+            );
+
+            pull_mock.assert();
+            status_mock.assert();
+            pull_mock.remove();
+            status_mock.remove();
+        }
+    }
+
+    #[test]
+    fn test_create_tag_and_release() {
+        let mut server = mockito::Server::new();
+
+        let tag_mock = server
+            .mock("POST", "/repos/test/tags")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(
+                r#"{"message":"","tag_name":"v1","target":"abc"}"#.into(),
+            ))
+            .with_status(201)
+            .with_body(r#"{"name":"v1"}"#)
+            .create();
+        let release_mock = server
+            .mock("POST", "/repos/test/releases")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(
+                r#"{"body":"notes","name":"v1","tag_name":"v1"}"#.into(),
+            ))
+            .with_status(201)
+            .with_body(r#"{"html_url":"https://example.com/releases/tag/v1"}"#)
+            .create();
+
+        let gitea = Gitea::new(server.url(), "test".into(), "test".into());
+        gitea
+            .create_tag(
+                &Tag {
+                    name: "v1".into(),
+                    message: None,
+                },
+                "abc",
+            )
+            .unwrap();
+        assert_eq!(
+            gitea
+                .create_release(&Release {
+                    tag: "v1".into(),
+                    name: "v1".into(),
+                    notes: "notes".into(),
+                })
+                .unwrap(),
+            "https://example.com/releases/tag/v1"
+        );
+
+        tag_mock.assert();
+        release_mock.assert();
+    }
+
+    #[test]
+    fn test_set_commit_status() {
+        let mut server = mockito::Server::new();
+
+        let status_mock = server
+            .mock("POST", "/repos/test/statuses/abc")
+            .match_header("authorization", "Basic dGVzdA==")
+            .match_body(Matcher::JsonString(r#"{"context":"deploy","description":"deployed","state":"success","target_url":""}"#.into()))
+            .with_status(201)
+            .with_body(r#"{"id":1}"#)
+            .create();
+
+        Gitea::new(server.url(), "test".into(), "test".into())
+            .set_commit_status(
+                "abc",
+                &CommitStatus {
+                    state: State::Success,
+                    context: "deploy".into(),
+                    target_url: None,
+                    description: Some("deployed".into()),
+                },
+            )
+            .unwrap();
+
+        status_mock.assert();
+    }
 }
@@ -1,14 +1,22 @@
-use crate::{commit::CommitRequest, repository::Repository};
-use anyhow::Result;
+use super::token::Token;
+use crate::{
+    commit::{CommitInfo, CommitRequest},
+    merge_request::{MergeRequest, MergeRequestInfo, MergeRequestState},
+    repository::{Conflict, Repository},
+    status::{CommitStatus, State},
+    tag::{Release, Tag},
+};
+use anyhow::{anyhow, bail, Result};
 use base64::{engine::general_purpose, Engine};
 use bytes::Bytes;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use reqwest::{
     blocking::Client,
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
     StatusCode,
 };
 use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap, str::FromStr};
 
 const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 const DEFAULT_GITLAB_API_URL: &str = "https://gitlab.com/api/v4";
@@ -26,12 +34,96 @@ pub fn default_api_url() -> String {
 
 #[derive(Deserialize)]
 struct CommitResponse {
+    id: String,
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestResponse {
+    iid: u64,
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct PipelineResponse {
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestStateResponse {
+    state: String,
+    head_pipeline: Option<PipelineResponse>,
+    /// the head of the source branch, which fast-forward merges land as
+    sha: Option<String>,
+    merge_commit_sha: Option<String>,
+    squash_commit_sha: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TagPayload {
+    tag_name: String,
+    #[serde(rename = "ref")]
+    reference: String,
+    /// makes the tag annotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReleasePayload {
+    tag_name: String,
+    name: String,
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct ReleaseLinks {
+    #[serde(rename = "self")]
     web_url: String,
 }
 
+#[derive(Deserialize)]
+struct ReleaseResponse {
+    #[serde(rename = "_links")]
+    links: ReleaseLinks,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusPayload {
+    state: &'static str,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UserResponse {
+    id: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct MergeRequestPayload {
+    source_branch: String,
+    target_branch: String,
+    title: String,
+    description: String,
+    /// comma-separated label names
+    #[serde(skip_serializing_if = "String::is_empty")]
+    labels: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    assignee_ids: Vec<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reviewer_ids: Vec<u64>,
+}
+
 #[derive(Debug, Serialize)]
 struct CommitPayload {
     branch: String,
+    /// creates branch from this one if it doesn't exist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_branch: Option<String>,
     commit_message: String,
     actions: Vec<CommitAction>,
     author_name: String,
@@ -42,8 +134,15 @@ struct CommitPayload {
 struct CommitAction {
     action: Action,
     file_path: String,
-    content: String,
-    encoding: Encoding,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>,
+    /// the last commit that changed the file when it was read, GitLab refuses the commit if it changed since
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_commit_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -51,6 +150,8 @@ struct CommitAction {
 enum Action {
     Create,
     Update,
+    Delete,
+    Move,
 }
 
 #[derive(Debug, Serialize)]
@@ -60,28 +161,83 @@ enum Encoding {
     Base64,
 }
 
+/// encodes content as text if possible, falling back to base64 for binary files
+fn encode(content: Bytes) -> (Encoding, String) {
+    match std::str::from_utf8(&content) {
+        Ok(content) => (Encoding::Text, content.into()),
+        Err(_) => (Encoding::Base64, general_purpose::STANDARD.encode(content)),
+    }
+}
+
+/// how the token is sent to GitLab
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Auth {
+    /// personal and OAuth access tokens, sent as `Authorization: Bearer`
+    #[serde(alias = "oauth")]
+    Bearer,
+    /// project, group and personal access tokens, sent as `PRIVATE-TOKEN`
+    PrivateToken,
+    /// the `CI_JOB_TOKEN` of a GitLab CI job, only accepted by the endpoints its scope allows
+    JobToken,
+}
+
+impl FromStr for Auth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bearer" | "oauth" => Ok(Auth::Bearer),
+            "private_token" => Ok(Auth::PrivateToken),
+            "job_token" => Ok(Auth::JobToken),
+            _ => bail!(
+                "invalid GitLab auth {}, expected bearer, oauth, private_token or job_token",
+                s
+            ),
+        }
+    }
+}
+
 pub struct Gitlab {
     api_url: String,
     project_id: String,
-    token: String,
+    token: Token,
+    auth: Auth,
     client: Client,
+    /// last commit IDs of the files as they were read, to detect concurrent changes on commit
+    last_commit_ids: RefCell<HashMap<String, String>>,
 }
 
 impl Gitlab {
-    pub fn new(api_url: impl ToString, project_id: impl ToString, token: impl ToString) -> Self {
+    pub fn new(api_url: impl ToString, project_id: impl ToString, token: impl Into<Token>) -> Self {
         Self {
             api_url: api_url.to_string(),
             project_id: project_id.to_string(),
-            token: token.to_string(),
+            token: token.into(),
+            auth: Auth::Bearer,
             client: Client::builder()
                 .user_agent(USER_AGENT)
                 .build()
                 .expect("failed to contruct HTTP client"),
+            last_commit_ids: RefCell::default(),
         }
     }
 
-    fn auth_header(&self) -> String {
-        format!("Bearer {}", self.token)
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn auth_headers(&self) -> Result<HeaderMap> {
+        let token = self.token.get()?;
+        let (name, value) = match self.auth {
+            Auth::Bearer => (AUTHORIZATION, format!("Bearer {token}")),
+            Auth::PrivateToken => (HeaderName::from_static("private-token"), token),
+            Auth::JobToken => (HeaderName::from_static("job-token"), token),
+        };
+        let mut value = HeaderValue::from_str(&value)?;
+        value.set_sensitive(true);
+        Ok(HeaderMap::from_iter([(name, value)]))
     }
 
     fn file_to_action(
@@ -96,19 +252,35 @@ impl Gitlab {
         } else {
             Action::Create
         };
-        let (encoding, content) = match std::str::from_utf8(&content) {
-            Ok(content) => (Encoding::Text, content.into()),
-            Err(_) => (Encoding::Base64, general_purpose::STANDARD.encode(content)),
-        };
+        let (encoding, content) = encode(content);
 
         Ok(CommitAction {
+            last_commit_id: self.last_commit_ids.borrow().get(&file_path).cloned(),
             action,
             file_path,
-            content,
-            encoding,
+            previous_path: None,
+            content: Some(content),
+            encoding: Some(encoding),
         })
     }
 
+    /// GitLab references users by ID when assigning merge requests
+    fn user_id(&self, username: &str) -> Result<u64> {
+        let users: Vec<UserResponse> = self
+            .client
+            .get(format!("{}/users", self.api_url))
+            .query(&[("username", username)])
+            .headers(self.auth_headers()?)
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        users
+            .first()
+            .map(|user| user.id)
+            .ok_or_else(|| anyhow!("user {} not found", username))
+    }
+
     fn check_file_exists(&self, path: &str, reference: &str) -> Result<bool> {
         let response = self
             .client
@@ -119,7 +291,7 @@ impl Gitlab {
                 utf8_percent_encode(path, FRAGMENT)
             ))
             .query(&[("ref", reference)])
-            .header(AUTHORIZATION, self.auth_header())
+            .headers(self.auth_headers()?)
             .send()?;
 
         if response.status() == StatusCode::NOT_FOUND {
@@ -133,6 +305,27 @@ impl Gitlab {
 }
 
 impl Repository for Gitlab {
+    fn branch_exists(&self, branch: &str) -> Result<bool> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/projects/{}/repository/branches/{}",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+                utf8_percent_encode(branch, FRAGMENT)
+            ))
+            .headers(self.auth_headers()?)
+            .send()?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        response.error_for_status()?;
+
+        Ok(true)
+    }
+
     fn get(&self, path: &str, reference: &str) -> Result<Bytes> {
         log::debug!(
             "fetching file path={path} project={} ref={reference} api_url={}",
@@ -149,14 +342,24 @@ impl Repository for Gitlab {
                 utf8_percent_encode(path, FRAGMENT)
             ))
             .query(&[("ref", reference)])
-            .header(AUTHORIZATION, self.auth_header())
+            .headers(self.auth_headers()?)
             .send()?
             .error_for_status()?;
 
+        if let Some(last_commit_id) = response
+            .headers()
+            .get("x-gitlab-last-commit-id")
+            .and_then(|id| id.to_str().ok())
+        {
+            self.last_commit_ids
+                .borrow_mut()
+                .insert(path.into(), last_commit_id.into());
+        }
+
         Ok(response.bytes()?)
     }
 
-    fn commit(&mut self, payload: CommitRequest) -> Result<()> {
+    fn commit(&mut self, payload: CommitRequest) -> Result<CommitInfo> {
         log::debug!(
             "committing changes author={} ref={} message={} project={} api_url={}",
             payload.author,
@@ -166,13 +369,47 @@ impl Repository for Gitlab {
             self.api_url
         );
 
-        let (author_name, author_email) = payload.split_author();
+        if let Some(committer) = &payload.committer {
+            log::warn!("GitLab commits as the token's user, ignoring committer {committer}");
+        }
+        let files = payload.paths();
+
+        // when creating the branch, files are checked against the one it starts from
+        let reference = payload.start_branch.as_deref().unwrap_or(&payload.branch);
 
-        let actions = payload
-            .files
+        let mut actions = payload
+            .moved
             .into_iter()
-            .map(|(file, content)| self.file_to_action(file, &payload.branch, content))
-            .collect::<Result<Vec<CommitAction>>>()?;
+            .map(|(file_path, previous_path)| CommitAction {
+                action: Action::Move,
+                file_path,
+                last_commit_id: self.last_commit_ids.borrow().get(&previous_path).cloned(),
+                previous_path: Some(previous_path),
+                content: None,
+                encoding: None,
+            })
+            .collect::<Vec<CommitAction>>();
+
+        for (file, content) in payload.files {
+            // moved files can be updated in the same action
+            match actions.iter_mut().find(|action| action.file_path == file) {
+                Some(action) => {
+                    let (encoding, content) = encode(content);
+                    action.content = Some(content);
+                    action.encoding = Some(encoding);
+                }
+                None => actions.push(self.file_to_action(file, reference, content)?),
+            }
+        }
+
+        actions.extend(payload.deleted.into_iter().map(|file_path| CommitAction {
+            action: Action::Delete,
+            last_commit_id: self.last_commit_ids.borrow().get(&file_path).cloned(),
+            file_path,
+            previous_path: None,
+            content: None,
+            encoding: None,
+        }));
 
         #[cfg(test)]
         let actions = {
@@ -185,13 +422,14 @@ impl Repository for Gitlab {
 
         let body = &CommitPayload {
             branch: payload.branch,
+            start_branch: payload.start_branch,
             commit_message: payload.message,
             actions,
-            author_name,
-            author_email,
+            author_name: payload.author.name,
+            author_email: payload.author.email,
         };
 
-        let CommitResponse { web_url } = self
+        let response = self
             .client
             .post(format!(
                 "{}/projects/{}/repository/commits",
@@ -199,14 +437,301 @@ impl Repository for Gitlab {
                 utf8_percent_encode(&self.project_id, FRAGMENT),
             ))
             .header(CONTENT_TYPE, "application/json")
-            .header(AUTHORIZATION, self.auth_header())
+            .headers(self.auth_headers()?)
+            .header(ACCEPT, "application/json")
+            .json(body)
+            .send()?;
+
+        // GitLab rejects the commit if a file was changed or created since it was read
+        if response.status() == StatusCode::BAD_REQUEST {
+            let message = response.text()?;
+            if message.contains("has changed since") || message.contains("already exists") {
+                return Err(Conflict(message).into());
+            }
+            return Err(anyhow!(message));
+        }
+
+        let CommitResponse { id, web_url } = response.error_for_status()?.json()?;
+
+        self.last_commit_ids.borrow_mut().clear();
+        log::info!("commit URL: {web_url}");
+
+        Ok(CommitInfo {
+            sha: id,
+            web_url,
+            files,
+            merge_request_url: None,
+            release_url: None,
+        })
+    }
+
+    fn delete_branch(&mut self, branch: &str) -> Result<()> {
+        log::debug!(
+            "deleting branch {branch} project={} api_url={}",
+            self.project_id,
+            self.api_url
+        );
+
+        self.client
+            .delete(format!(
+                "{}/projects/{}/repository/branches/{}",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+                utf8_percent_encode(branch, FRAGMENT)
+            ))
+            .headers(self.auth_headers()?)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn find_merge_request(
+        &self,
+        source_branch: &str,
+        target_branch: &str,
+    ) -> Result<Option<MergeRequestInfo>> {
+        let existing: Vec<MergeRequestResponse> = self
+            .client
+            .get(format!(
+                "{}/projects/{}/merge_requests",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+            ))
+            .query(&[
+                ("state", "opened"),
+                ("source_branch", source_branch),
+                ("target_branch", target_branch),
+            ])
+            .headers(self.auth_headers()?)
+            .send()?
+            .error_for_status()?
+            .json()?;
+        Ok(existing
+            .into_iter()
+            .next()
+            .map(|MergeRequestResponse { iid, web_url }| MergeRequestInfo {
+                number: iid,
+                web_url,
+            }))
+    }
+
+    fn open_merge_request(&self, request: &MergeRequest) -> Result<MergeRequestInfo> {
+        if let Some(existing) =
+            self.find_merge_request(&request.source_branch, &request.target_branch)?
+        {
+            log::info!("merge request already open: {}", existing.web_url);
+            return Ok(existing);
+        }
+
+        let url = format!(
+            "{}/projects/{}/merge_requests",
+            self.api_url,
+            utf8_percent_encode(&self.project_id, FRAGMENT),
+        );
+
+        log::debug!(
+            "opening merge request source={} target={} project={} api_url={}",
+            request.source_branch,
+            request.target_branch,
+            self.project_id,
+            self.api_url
+        );
+
+        let body = &MergeRequestPayload {
+            source_branch: request.source_branch.clone(),
+            target_branch: request.target_branch.clone(),
+            title: request.title.clone(),
+            description: request.description.clone(),
+            labels: request.labels.join(","),
+            assignee_ids: request
+                .assignees
+                .iter()
+                .map(|username| self.user_id(username))
+                .collect::<Result<_>>()?,
+            reviewer_ids: request
+                .reviewers
+                .iter()
+                .map(|username| self.user_id(username))
+                .collect::<Result<_>>()?,
+        };
+
+        let MergeRequestResponse { iid, web_url } = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers()?)
             .header(ACCEPT, "application/json")
             .json(body)
             .send()?
             .error_for_status()?
             .json()?;
 
-        log::info!("commit URL: {web_url}");
+        log::info!("merge request URL: {web_url}");
+
+        Ok(MergeRequestInfo {
+            number: iid,
+            web_url,
+        })
+    }
+
+    fn enable_auto_merge(&self, merge_request: &MergeRequestInfo) -> Result<()> {
+        log::debug!(
+            "enabling merge when pipeline succeeds iid={} project={} api_url={}",
+            merge_request.number,
+            self.project_id,
+            self.api_url
+        );
+
+        self.client
+            .put(format!(
+                "{}/projects/{}/merge_requests/{}/merge",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+                merge_request.number,
+            ))
+            .header(CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers()?)
+            .header(ACCEPT, "application/json")
+            .body(r#"{"merge_when_pipeline_succeeds":true}"#)
+            .send()?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    fn merge_request_state(&self, merge_request: &MergeRequestInfo) -> Result<MergeRequestState> {
+        let MergeRequestStateResponse {
+            state,
+            head_pipeline,
+            sha,
+            merge_commit_sha,
+            squash_commit_sha,
+        } = self
+            .client
+            .get(format!(
+                "{}/projects/{}/merge_requests/{}",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+                merge_request.number,
+            ))
+            .headers(self.auth_headers()?)
+            .header(ACCEPT, "application/json")
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        Ok(match state.as_str() {
+            "merged" => MergeRequestState::Merged(
+                merge_commit_sha
+                    .or(squash_commit_sha)
+                    .or(sha)
+                    .ok_or_else(|| {
+                        anyhow!("merge request {} has no commit", merge_request.web_url)
+                    })?,
+            ),
+            "closed" | "locked" => MergeRequestState::Closed,
+            _ => match head_pipeline {
+                Some(PipelineResponse { status }) if status == "failed" || status == "canceled" => {
+                    MergeRequestState::ChecksFailed
+                }
+                _ => MergeRequestState::Open,
+            },
+        })
+    }
+
+    fn create_tag(&self, tag: &Tag, sha: &str) -> Result<()> {
+        log::debug!(
+            "creating tag name={} sha={sha} project={} api_url={}",
+            tag.name,
+            self.project_id,
+            self.api_url
+        );
+
+        self.client
+            .post(format!(
+                "{}/projects/{}/repository/tags",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+            ))
+            .header(CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers()?)
+            .header(ACCEPT, "application/json")
+            .json(&TagPayload {
+                tag_name: tag.name.clone(),
+                reference: sha.into(),
+                message: tag.message.clone(),
+            })
+            .send()?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    fn create_release(&self, release: &Release) -> Result<String> {
+        log::debug!(
+            "creating release tag={} project={} api_url={}",
+            release.tag,
+            self.project_id,
+            self.api_url
+        );
+
+        let ReleaseResponse {
+            links: ReleaseLinks { web_url },
+        } = self
+            .client
+            .post(format!(
+                "{}/projects/{}/releases",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+            ))
+            .header(CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers()?)
+            .header(ACCEPT, "application/json")
+            .json(&ReleasePayload {
+                tag_name: release.tag.clone(),
+                name: release.name.clone(),
+                description: release.notes.clone(),
+            })
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        log::info!("release URL: {web_url}");
+
+        Ok(web_url)
+    }
+
+    fn set_commit_status(&self, sha: &str, status: &CommitStatus) -> Result<()> {
+        log::debug!(
+            "setting commit status sha={sha} state={:?} context={} project={} api_url={}",
+            status.state,
+            status.context,
+            self.project_id,
+            self.api_url
+        );
+
+        self.client
+            .post(format!(
+                "{}/projects/{}/statuses/{}",
+                self.api_url,
+                utf8_percent_encode(&self.project_id, FRAGMENT),
+                sha,
+            ))
+            .header(CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers()?)
+            .header(ACCEPT, "application/json")
+            .json(&StatusPayload {
+                state: match status.state {
+                    State::Pending => "pending",
+                    State::Success => "success",
+                    State::Failure => "failed",
+                },
+                name: status.context.clone(),
+                target_url: status.target_url.clone(),
+                description: status.description.clone(),
+            })
+            .send()?
+            .error_for_status()?;
 
         Ok(())
     }
@@ -214,6 +739,8 @@ impl Repository for Gitlab {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::{HashMap, HashSet};
+
     use bytes::{BufMut, BytesMut};
     use mockito::Matcher;
 
@@ -250,6 +777,32 @@ mod tests {
         get_mock.assert();
     }
 
+    #[test]
+    fn test_auth_headers() {
+        init();
+
+        let mut server = mockito::Server::new();
+        for (auth, header, value) in [
+            (Auth::Bearer, "authorization", "Bearer gitlab-token"),
+            (Auth::PrivateToken, "private-token", "gitlab-token"),
+            (Auth::JobToken, "job-token", "gitlab-token"),
+        ] {
+            let get_mock = server
+                .mock("GET", "/projects/test/repository/files/test/raw?ref=main")
+                .match_header(header, value)
+                .with_body("hello!")
+                .create();
+
+            Gitlab::new(server.url(), "test", "gitlab-token")
+                .with_auth(auth)
+                .get("test", "main")
+                .unwrap();
+
+            get_mock.assert();
+            get_mock.remove();
+        }
+    }
+
     #[test]
     fn test_commit_new() {
         init();
@@ -302,26 +855,40 @@ mod tests {
             })))
             .with_header("content-type", "application/json")
             .with_body(
-                serde_json::to_vec(&serde_json::json!({ "web_url": "https://example.com" }))
-                    .unwrap(),
+                serde_json::to_vec(
+                    &serde_json::json!({ "id": "abc", "web_url": "https://example.com" }),
+                )
+                .unwrap(),
             )
             .create();
 
         let mut non_utf8 = BytesMut::new();
         non_utf8.put_u16(1234);
 
-        Gitlab::new(server.url(), "test/test", "gitlab-token")
+        let info = Gitlab::new(server.url(), "test/test", "gitlab-token")
             .commit(CommitRequest {
                 branch: "main".into(),
-                author: "test <author@email.tld>".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
                 message: "test".into(),
                 files: FileList::from([
                     ("test/test.txt".into(), "test".into()),
                     ("test.bin".into(), non_utf8.into()),
                 ]),
+                ..Default::default()
             })
             .unwrap();
 
+        assert_eq!(
+            info,
+            CommitInfo {
+                sha: "abc".into(),
+                web_url: "https://example.com".into(),
+                files: vec!["test.bin".into(), "test/test.txt".into()],
+                merge_request_url: None,
+                release_url: None,
+            }
+        );
+
         get_txt_mock.assert();
         get_bin_mock.assert();
         put_mock.assert();
@@ -377,8 +944,10 @@ mod tests {
             })))
             .with_header("content-type", "application/json")
             .with_body(
-                serde_json::to_vec(&serde_json::json!({ "web_url": "https://example.com" }))
-                    .unwrap(),
+                serde_json::to_vec(
+                    &serde_json::json!({ "id": "abc", "web_url": "https://example.com" }),
+                )
+                .unwrap(),
             )
             .create();
 
@@ -388,12 +957,13 @@ mod tests {
         Gitlab::new(server.url(), "test/test", "gitlab-token")
             .commit(CommitRequest {
                 branch: "main".into(),
-                author: "test <author@email.tld>".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
                 message: "test".into(),
                 files: FileList::from([
                     ("test/test.txt".into(), "test".into()),
                     ("test.bin".into(), non_utf8.into()),
                 ]),
+                ..Default::default()
             })
             .unwrap();
 
@@ -401,4 +971,418 @@ mod tests {
         get_bin_mock.assert();
         put_mock.assert();
     }
+
+    #[test]
+    fn test_commit_move_delete() {
+        init();
+
+        let mut server = mockito::Server::new();
+
+        let put_mock = server
+            .mock("POST", "/projects/test%2Ftest/repository/commits")
+            .match_header("authorization", "Bearer gitlab-token")
+            .match_body(Matcher::Json(serde_json::json!({
+                "branch": "main",
+                "commit_message": "test",
+                "actions": [
+                    {
+                        "action": "move",
+                        "file_path": "moved.txt",
+                        "previous_path": "test.txt",
+                    },
+                    {
+                        "action": "delete",
+                        "file_path": "old.txt",
+                    },
+                    {
+                        "action": "move",
+                        "file_path": "updated.txt",
+                        "previous_path": "test2.txt",
+                        "content": "test",
+                        "encoding": "text"
+                    },
+                ],
+                "author_name": "test",
+                "author_email": "author@email.tld",
+            })))
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::to_vec(
+                    &serde_json::json!({ "id": "abc", "web_url": "https://example.com" }),
+                )
+                .unwrap(),
+            )
+            .create();
+
+        Gitlab::new(server.url(), "test/test", "gitlab-token")
+            .commit(CommitRequest {
+                branch: "main".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                files: FileList::from([("updated.txt".into(), "test".into())]),
+                deleted: HashSet::from(["old.txt".into()]),
+                moved: HashMap::from([
+                    ("moved.txt".into(), "test.txt".into()),
+                    ("updated.txt".into(), "test2.txt".into()),
+                ]),
+                ..Default::default()
+            })
+            .unwrap();
+
+        put_mock.assert();
+    }
+
+    #[test]
+    fn test_commit_conflict() {
+        init();
+
+        let mut server = mockito::Server::new();
+
+        let get_mock = server
+            .mock(
+                "GET",
+                "/projects/test%2Ftest/repository/files/test.txt/raw?ref=main",
+            )
+            .match_header("authorization", "Bearer gitlab-token")
+            .with_header("x-gitlab-last-commit-id", "abc123")
+            .with_body("test")
+            .create();
+        let head_mock = server
+            .mock(
+                "HEAD",
+                "/projects/test%2Ftest/repository/files/test.txt/raw?ref=main",
+            )
+            .match_header("authorization", "Bearer gitlab-token")
+            .create();
+        let put_mock = server
+            .mock("POST", "/projects/test%2Ftest/repository/commits")
+            .match_header("authorization", "Bearer gitlab-token")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "actions": [
+                    {
+                        "action": "update",
+                        "file_path": "test.txt",
+                        "content": "changed",
+                        "encoding": "text",
+                        "last_commit_id": "abc123"
+                    },
+                ],
+            })))
+            .with_status(400)
+            .with_body(r#"{"message":"You are attempting to update a file that has changed since you started editing it."}"#)
+            .create();
+
+        let mut gitlab = Gitlab::new(server.url(), "test/test", "gitlab-token");
+        gitlab.get("test.txt", "main").unwrap();
+        let err = gitlab
+            .commit(CommitRequest {
+                branch: "main".into(),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                files: FileList::from([("test.txt".into(), "changed".into())]),
+                ..Default::default()
+            })
+            .unwrap_err();
+
+        assert!(err.is::<Conflict>());
+        get_mock.assert();
+        head_mock.assert();
+        put_mock.assert();
+    }
+
+    #[test]
+    fn test_commit_new_branch() {
+        init();
+
+        let mut server = mockito::Server::new();
+
+        let branch_mock = server
+            .mock(
+                "GET",
+                "/projects/test%2Ftest/repository/branches/feature%2Ftest",
+            )
+            .match_header("authorization", "Bearer gitlab-token")
+            .with_status(404)
+            .create();
+        let head_mock = server
+            .mock(
+                "HEAD",
+                "/projects/test%2Ftest/repository/files/test.txt/raw?ref=main",
+            )
+            .match_header("authorization", "Bearer gitlab-token")
+            .create();
+        let put_mock = server
+            .mock("POST", "/projects/test%2Ftest/repository/commits")
+            .match_header("authorization", "Bearer gitlab-token")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "branch": "feature/test",
+                "start_branch": "main",
+                "actions": [
+                    {
+                        "action": "update",
+                        "file_path": "test.txt",
+                        "content": "test",
+                        "encoding": "text"
+                    },
+                ],
+            })))
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::to_vec(
+                    &serde_json::json!({ "id": "abc", "web_url": "https://example.com" }),
+                )
+                .unwrap(),
+            )
+            .create();
+
+        let mut gitlab = Gitlab::new(server.url(), "test/test", "gitlab-token");
+        assert!(!gitlab.branch_exists("feature/test").unwrap());
+        gitlab
+            .commit(CommitRequest {
+                branch: "feature/test".into(),
+                start_branch: Some("main".into()),
+                author: "test <author@email.tld>".parse().unwrap(),
+                message: "test".into(),
+                files: FileList::from([("test.txt".into(), "test".into())]),
+                ..Default::default()
+            })
+            .unwrap();
+
+        branch_mock.assert();
+        head_mock.assert();
+        put_mock.assert();
+    }
+
+    #[test]
+    fn test_delete_branch() {
+        init();
+
+        let mut server = mockito::Server::new();
+        let delete_mock = server
+            .mock(
+                "DELETE",
+                "/projects/test%2Ftest/repository/branches/shipit%2Fmain",
+            )
+            .match_header("authorization", "Bearer gitlab-token")
+            .with_status(204)
+            .create();
+
+        Gitlab::new(server.url(), "test/test", "gitlab-token")
+            .delete_branch("shipit/main")
+            .unwrap();
+
+        delete_mock.assert();
+    }
+
+    #[test]
+    fn test_open_merge_request() {
+        init();
+
+        let mut server = mockito::Server::new();
+
+        let list_mock = server
+            .mock("GET", "/projects/test%2Ftest/merge_requests")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("state".into(), "opened".into()),
+                Matcher::UrlEncoded("source_branch".into(), "shipit/main".into()),
+                Matcher::UrlEncoded("target_branch".into(), "main".into()),
+            ]))
+            .match_header("authorization", "Bearer gitlab-token")
+            .with_body("[]")
+            .create();
+        let user_mock = server
+            .mock("GET", "/users?username=reviewer")
+            .match_header("authorization", "Bearer gitlab-token")
+            .with_body(r#"[{"id": 42, "username": "reviewer"}]"#)
+            .create();
+        let create_mock = server
+            .mock("POST", "/projects/test%2Ftest/merge_requests")
+            .match_header("authorization", "Bearer gitlab-token")
+            .match_body(Matcher::Json(serde_json::json!({
+                "source_branch": "shipit/main",
+                "target_branch": "main",
+                "title": "Update deployment",
+                "description": "",
+                "labels": "deploy,production",
+                "reviewer_ids": [42],
+            })))
+            .with_body(r#"{"iid": 7, "web_url": "https://example.com/mr/7"}"#)
+            .create();
+
+        let request = MergeRequest {
+            source_branch: "shipit/main".into(),
+            target_branch: "main".into(),
+            title: "Update deployment".into(),
+            labels: vec!["deploy".into(), "production".into()],
+            reviewers: vec!["reviewer".into()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Gitlab::new(server.url(), "test/test", "gitlab-token")
+                .open_merge_request(&request)
+                .unwrap(),
+            MergeRequestInfo {
+                number: 7,
+                web_url: "https://example.com/mr/7".into()
+            }
+        );
+
+        list_mock.assert();
+        user_mock.assert();
+        create_mock.assert();
+        list_mock.remove();
+
+        // an open merge request is reused
+        server
+            .mock("GET", "/projects/test%2Ftest/merge_requests")
+            .match_query(Matcher::Any)
+            .with_body(r#"[{"iid": 7, "web_url": "https://example.com/mr/7"}]"#)
+            .create();
+
+        assert_eq!(
+            Gitlab::new(server.url(), "test/test", "gitlab-token")
+                .open_merge_request(&request)
+                .unwrap()
+                .number,
+            7
+        );
+        create_mock.assert();
+    }
+
+    #[test]
+    fn test_auto_merge() {
+        let mut server = mockito::Server::new();
+        let merge_request = MergeRequestInfo {
+            number: 2,
+            web_url: "https://gitlab.com/test/-/merge_requests/2".into(),
+        };
+
+        let merge_mock = server
+            .mock("PUT", "/projects/test/merge_requests/2/merge")
+            .match_header("authorization", "Bearer test")
+            .match_body(r#"{"merge_when_pipeline_succeeds":true}"#)
+            .with_body(r#"{"iid":2}"#)
+            .create();
+
+        Gitlab::new(server.url(), "test", "test")
+            .enable_auto_merge(&merge_request)
+            .unwrap();
+
+        merge_mock.assert();
+
+        for (body, state) in [
+            (
+                r#"{"state":"opened","head_pipeline":null}"#,
+                MergeRequestState::Open,
+            ),
+            (
+                r#"{"state":"opened","head_pipeline":{"status":"running"}}"#,
+                MergeRequestState::Open,
+            ),
+            (
+                r#"{"state":"opened","head_pipeline":{"status":"failed"}}"#,
+                MergeRequestState::ChecksFailed,
+            ),
+            (
+                r#"{"state":"merged","head_pipeline":{"status":"success"},"sha":"abc","merge_commit_sha":"def"}"#,
+                MergeRequestState::Merged("def".into()),
+            ),
+            (
+                r#"{"state":"merged","head_pipeline":{"status":"success"},"sha":"abc","merge_commit_sha":null}"#,
+                MergeRequestState::Merged("abc".into()),
+            ),
+            (
+                r#"{"state":"closed","head_pipeline":null}"#,
+                MergeRequestState::Closed,
+            ),
+        ] {
+            let state_mock = server
+                .mock("GET", "/projects/test/merge_requests/2")
+                .match_header("authorization", "Bearer test")
+                .with_body(body)
+                .create();
+
+            assert_eq!(
+                Gitlab::new(server.url(), "test", "test")
+                    .merge_request_state(&merge_request)
+                    .unwrap(),
+                state
+            );
+
+            state_mock.assert();
+            state_mock.remove();
+        }
+    }
+
+    #[test]
+    fn test_create_tag_and_release() {
+        let mut server = mockito::Server::new();
+
+        let tag_mock = server
+            .mock("POST", "/projects/test/repository/tags")
+            .match_header("authorization", "Bearer test")
+            .match_body(r#"{"tag_name":"v1","ref":"abc","message":"deploy v1"}"#)
+            .with_status(201)
+            .with_body(r#"{"name":"v1"}"#)
+            .create();
+        let release_mock = server
+            .mock("POST", "/projects/test/releases")
+            .match_header("authorization", "Bearer test")
+            .match_body(r#"{"tag_name":"v1","name":"v1","description":"notes"}"#)
+            .with_status(201)
+            .with_body(r#"{"_links":{"self":"https://gitlab.com/test/-/releases/v1"}}"#)
+            .create();
+
+        let gitlab = Gitlab::new(server.url(), "test", "test");
+        gitlab
+            .create_tag(
+                &Tag {
+                    name: "v1".into(),
+                    message: Some("deploy v1".into()),
+                },
+                "abc",
+            )
+            .unwrap();
+        assert_eq!(
+            gitlab
+                .create_release(&Release {
+                    tag: "v1".into(),
+                    name: "v1".into(),
+                    notes: "notes".into(),
+                })
+                .unwrap(),
+            "https://gitlab.com/test/-/releases/v1"
+        );
+
+        tag_mock.assert();
+        release_mock.assert();
+    }
+
+    #[test]
+    fn test_set_commit_status() {
+        let mut server = mockito::Server::new();
+
+        let status_mock = server
+            .mock("POST", "/projects/test/statuses/abc")
+            .match_header("authorization", "Bearer test")
+            .match_body(r#"{"state":"failed","name":"deploy","target_url":"https://example.com/pipelines/1"}"#)
+            .with_status(201)
+            .with_body(r#"{"id":1}"#)
+            .create();
+
+        Gitlab::new(server.url(), "test", "test")
+            .set_commit_status(
+                "abc",
+                &CommitStatus {
+                    state: State::Failure,
+                    context: "deploy".into(),
+                    target_url: Some("https://example.com/pipelines/1".into()),
+                    description: None,
+                },
+            )
+            .unwrap();
+
+        status_mock.assert();
+    }
 }
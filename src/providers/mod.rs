@@ -1,26 +1,49 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Url;
 use serde::Deserialize;
 
 use crate::repository::Repository;
 
-use self::{gitea::Gitea, gitlab::Gitlab};
+use self::{
+    gitea::Gitea,
+    gitlab::Gitlab,
+    token::{Credentials, TokenSource},
+};
 
 mod gitea;
 mod gitlab;
+mod token;
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 #[serde(tag = "provider", rename_all = "snake_case")]
 pub enum Provider {
+    /// also used for Forgejo, which shares its API
+    #[serde(alias = "forgejo")]
     Gitea {
         api_url: String,
         project_id: String,
-        token: String,
+        #[serde(flatten)]
+        token: TokenSource,
+        #[serde(default)]
+        auth: gitea::Auth,
     },
     #[serde(rename = "gitlab")]
     GitLab {
         #[serde(default = "gitlab::default_api_url")]
         api_url: String,
         project_id: String,
-        token: String,
+        #[serde(flatten)]
+        token: TokenSource,
+        /// defaults to the job token inside GitLab CI when no token is set, to bearer otherwise
+        auth: Option<gitlab::Auth>,
+    },
+    /// inferred from the CI environment, targeting the CI project unless project_id is set
+    Auto {
+        project_id: Option<String>,
+        #[serde(flatten)]
+        token: TokenSource,
     },
 }
 
@@ -29,21 +52,370 @@ impl Provider {
         match self {
             Provider::Gitea { .. } => "gitea",
             Provider::GitLab { .. } => "gitlab",
+            Provider::Auto { .. } => "auto",
         }
     }
 }
 
-pub fn get_repository(provider: Provider) -> Box<dyn Repository> {
-    match provider {
+/// parses connection strings like `gitlab://gitlab.example.com/group/project?token=env:GITLAB_TOKEN`,
+/// where the scheme can be suffixed with `+http` or `+https` (the default),
+/// Gitea and Forgejo also take `auth=basic|token|bearer`,
+/// GitLab `auth=bearer|oauth|private_token|job_token`.
+/// `auto:` and `auto:group/project` infer the rest from the CI environment
+impl FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let url = Url::parse(s).with_context(|| "invalid provider URL")?;
+        let token = match url.query_pairs().find(|(key, _)| key == "token") {
+            Some((_, reference)) => token_reference(&reference)?,
+            None => TokenSource::default(),
+        };
+        if url.scheme() == "auto" {
+            let project_id = url.path().trim_matches('/');
+            return Ok(Provider::Auto {
+                project_id: (!project_id.is_empty()).then(|| project_id.into()),
+                token,
+            });
+        }
+
+        let (kind, transport) = url
+            .scheme()
+            .split_once('+')
+            .unwrap_or((url.scheme(), "https"));
+        if !matches!(transport, "http" | "https") {
+            bail!(
+                "invalid provider transport {}, expected http or https",
+                transport
+            );
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("missing host in provider URL"))?;
+        let base = match url.port() {
+            Some(port) => format!("{transport}://{host}:{port}"),
+            None => format!("{transport}://{host}"),
+        };
+        let project_id = url.path().trim_matches('/').to_string();
+        if project_id.is_empty() {
+            bail!("missing project in provider URL");
+        }
+
+        let auth = url
+            .query_pairs()
+            .find(|(key, _)| key == "auth")
+            .map(|(_, auth)| auth.into_owned());
+
+        Ok(match kind {
+            "gitea" | "forgejo" => Provider::Gitea {
+                api_url: format!("{base}/api/v1"),
+                project_id,
+                token,
+                auth: auth
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?
+                    .unwrap_or_default(),
+            },
+            "gitlab" => Provider::GitLab {
+                api_url: format!("{base}/api/v4"),
+                project_id,
+                token,
+                auth: auth.as_deref().map(str::parse).transpose()?,
+            },
+            _ => bail!(
+                "unknown provider {}, expected gitea, forgejo, gitlab or auto",
+                kind
+            ),
+        })
+    }
+}
+
+/// parses provider info, either as JSON, as a connection string or as `auto`
+pub fn parse(provider: &str) -> Result<Provider> {
+    if provider.trim_start().starts_with('{') {
+        Ok(serde_json::from_str(provider)?)
+    } else if provider == "auto" {
+        Ok(Provider::Auto {
+            project_id: None,
+            token: TokenSource::default(),
+        })
+    } else {
+        provider.parse()
+    }
+}
+
+/// parses a token reference, either `env:NAME` or `file:PATH`, so that tokens stay out of URLs
+fn token_reference(reference: &str) -> Result<TokenSource> {
+    match reference.split_once(':') {
+        Some(("env", name)) => Ok(TokenSource {
+            token_env: Some(name.into()),
+            ..Default::default()
+        }),
+        Some(("file", path)) => Ok(TokenSource {
+            token_file: Some(path.into()),
+            ..Default::default()
+        }),
+        _ => bail!("invalid token reference, expected env:NAME or file:PATH"),
+    }
+}
+
+/// infers the provider of the CI project from the variables of GitLab CI or Gitea/Forgejo Actions
+fn detect(
+    project_id: Option<String>,
+    mut token: TokenSource,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Provider> {
+    let env = |name: &str| env(name).filter(|value| !value.is_empty());
+    let missing = |name: &str| anyhow!("could not detect the provider, {} is not set", name);
+
+    if env("GITLAB_CI").is_some() {
+        Ok(Provider::GitLab {
+            api_url: env("CI_API_V4_URL").ok_or_else(|| missing("CI_API_V4_URL"))?,
+            project_id: project_id
+                .or_else(|| env("CI_PROJECT_PATH"))
+                .ok_or_else(|| missing("CI_PROJECT_PATH"))?,
+            token,
+            // falls back to CI_JOB_TOKEN when neither a token nor ~/.netrc is set
+            auth: None,
+        })
+    } else if env("GITEA_ACTIONS").is_some() || env("FORGEJO_ACTIONS").is_some() {
+        // the workflow token has to be passed to the step, as GITEA_TOKEN or GITHUB_TOKEN
+        if token.is_empty() {
+            token.token_env = ["GITEA_TOKEN", "FORGEJO_TOKEN", "GITHUB_TOKEN"]
+                .into_iter()
+                .find(|name| env(name).is_some())
+                .map(String::from);
+        }
+        Ok(Provider::Gitea {
+            api_url: env("GITHUB_API_URL").ok_or_else(|| missing("GITHUB_API_URL"))?,
+            project_id: project_id
+                .or_else(|| env("GITHUB_REPOSITORY"))
+                .ok_or_else(|| missing("GITHUB_REPOSITORY"))?,
+            token,
+            auth: gitea::Auth::Token,
+        })
+    } else if env("GITHUB_ACTIONS").is_some() {
+        bail!("GitHub is not supported as a provider yet")
+    } else {
+        bail!("could not detect the provider, not running in GitLab CI or Gitea/Forgejo Actions")
+    }
+}
+
+pub fn get_repository(provider: Provider) -> Result<Box<dyn Repository>> {
+    Ok(match provider {
         Provider::Gitea {
             api_url,
             project_id,
             token,
-        } => Box::new(Gitea::new(api_url, project_id, token)),
+            auth,
+        } => {
+            let token = token.resolve(&api_url, |credentials| gitea_netrc(auth, credentials))?;
+            Box::new(Gitea::new(api_url, project_id, token).with_auth(auth))
+        }
         Provider::GitLab {
+            api_url,
+            project_id,
+            mut token,
+            auth,
+        } => {
+            // job tokens can't commit or open merge requests, so inside GitLab CI they're only
+            // used when asked for, or as a last resort when neither a token nor ~/.netrc is set
+            let auth = auth.unwrap_or_else(|| {
+                match token.is_empty()
+                    && std::env::var_os("CI_JOB_TOKEN").is_some()
+                    && token::netrc_credentials(&api_url).is_err()
+                {
+                    true => {
+                        log::warn!("no token set, authenticating to GitLab with CI_JOB_TOKEN (auth: job_token), which may not be allowed to push");
+                        gitlab::Auth::JobToken
+                    }
+                    false => gitlab::Auth::Bearer,
+                }
+            });
+            if auth == gitlab::Auth::JobToken && token.is_empty() {
+                token.token_env = Some("CI_JOB_TOKEN".into());
+            }
+            log::debug!("authenticating to GitLab with {:?}", auth);
+
+            let token = token.resolve(&api_url, |credentials| Ok(credentials.password))?;
+            Box::new(Gitlab::new(api_url, project_id, token).with_auth(auth))
+        }
+        Provider::Auto { project_id, token } => {
+            let provider = detect(project_id, token, |name| std::env::var(name).ok())?;
+            log::debug!("detected {} provider {:?}", provider.name(), provider);
+            return get_repository(provider);
+        }
+    })
+}
+
+/// Basic auth needs the login too, so netrc entries are passed as login:password
+fn gitea_netrc(auth: gitea::Auth, Credentials { login, password }: Credentials) -> Result<String> {
+    match (auth, login) {
+        (gitea::Auth::Basic, Some(login)) => Ok(format!("{login}:{password}")),
+        (gitea::Auth::Basic, None) => {
+            bail!("Basic auth needs a login, add one or use token auth (auth: token)")
+        }
+        (_, _) => Ok(password),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn detect_with(project_id: Option<&str>, vars: &[(&str, &str)]) -> Result<Provider> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        detect(
+            project_id.map(String::from),
+            TokenSource::default(),
+            |name| vars.get(name).map(|value| value.to_string()),
+        )
+    }
+
+    #[test]
+    fn test_detect() {
+        let gitlab = [
+            ("GITLAB_CI", "true"),
+            ("CI_API_V4_URL", "https://gitlab.example.com/api/v4"),
+            ("CI_PROJECT_PATH", "group/app"),
+        ];
+        let Provider::GitLab {
+            api_url,
+            project_id,
+            auth,
+            ..
+        } = detect_with(None, &gitlab).unwrap()
+        else {
+            panic!("expected a GitLab provider");
+        };
+        assert_eq!(api_url, "https://gitlab.example.com/api/v4");
+        assert_eq!(project_id, "group/app");
+        assert_eq!(auth, None);
+
+        let Provider::GitLab { project_id, .. } =
+            detect_with(Some("group/gitops"), &gitlab).unwrap()
+        else {
+            panic!("expected a GitLab provider");
+        };
+        assert_eq!(project_id, "group/gitops");
+
+        let Provider::Gitea {
+            api_url,
+            project_id,
+            token,
+            auth,
+        } = detect_with(
+            Some("owner/gitops"),
+            &[
+                ("GITHUB_ACTIONS", "true"),
+                ("GITEA_ACTIONS", "true"),
+                ("GITHUB_API_URL", "https://gitea.example.com/api/v1"),
+                ("GITHUB_REPOSITORY", "owner/app"),
+                ("GITHUB_TOKEN", "secret"),
+            ],
+        )
+        .unwrap()
+        else {
+            panic!("expected a Gitea provider");
+        };
+        assert_eq!(api_url, "https://gitea.example.com/api/v1");
+        assert_eq!(project_id, "owner/gitops");
+        assert_eq!(token.token_env.as_deref(), Some("GITHUB_TOKEN"));
+        assert_eq!(auth, gitea::Auth::Token);
+
+        assert!(detect_with(None, &[("GITHUB_ACTIONS", "true")]).is_err());
+        assert!(detect_with(None, &[("GITLAB_CI", "true")]).is_err());
+        assert!(detect_with(None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_connection_string() {
+        let Provider::GitLab {
             api_url,
             project_id,
             token,
-        } => Box::new(Gitlab::new(api_url, project_id, token)),
+            auth,
+        } = parse("gitlab://gitlab.example.com/group/project?token=env:SHIPIT_TEST_URL_TOKEN")
+            .unwrap()
+        else {
+            panic!("expected a GitLab provider");
+        };
+        assert_eq!(api_url, "https://gitlab.example.com/api/v4");
+        assert_eq!(project_id, "group/project");
+        assert_eq!(token.token_env.as_deref(), Some("SHIPIT_TEST_URL_TOKEN"));
+        assert_eq!(auth, None);
+
+        let Provider::Gitea {
+            api_url,
+            project_id,
+            auth,
+            ..
+        } = parse("forgejo+http://localhost:3000/owner/repo/?token=env:SHIPIT_TEST_URL_TOKEN&auth=token")
+            .unwrap()
+        else {
+            panic!("expected a Gitea provider");
+        };
+        assert_eq!(api_url, "http://localhost:3000/api/v1");
+        assert_eq!(project_id, "owner/repo");
+        assert_eq!(auth, gitea::Auth::Token);
+
+        assert!(
+            parse(r#"{"provider":"gitea","api_url":"a","project_id":"b","token":"c"}"#).is_ok()
+        );
+        assert!(parse("github://github.com/owner/repo?token=env:SHIPIT_TEST_URL_TOKEN").is_err());
+        assert!(parse("gitea+ftp://host/owner/repo?token=env:SHIPIT_TEST_URL_TOKEN").is_err());
+        assert!(parse("gitea://host?token=env:SHIPIT_TEST_URL_TOKEN").is_err());
+        assert!(parse("gitea://host/owner/repo?token=hunter2").is_err());
+        assert!(parse("gitea://host/owner/repo").is_ok());
+        assert!(parse("gitea://host/owner/repo?auth=digest").is_err());
+        assert!(matches!(
+            parse("auto:group/project").unwrap(),
+            Provider::Auto {
+                project_id: Some(project_id),
+                ..
+            } if project_id == "group/project"
+        ));
+        assert!(matches!(
+            parse("auto").unwrap(),
+            Provider::Auto {
+                project_id: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse("gitlab://gitlab.com/group/project?auth=job_token").unwrap(),
+            Provider::GitLab {
+                auth: Some(gitlab::Auth::JobToken),
+                ..
+            }
+        ));
+        assert!(parse(
+            r#"{"provider":"forgejo","api_url":"a","project_id":"b","token":"c","auth":"bearer"}"#
+        )
+        .is_ok());
+    }
+    #[test]
+    fn test_gitea_netrc() {
+        let credentials = |login: Option<&str>| Credentials {
+            login: login.map(String::from),
+            password: "hunter2".into(),
+        };
+        assert_eq!(
+            gitea_netrc(gitea::Auth::Basic, credentials(Some("shipit"))).unwrap(),
+            "shipit:hunter2"
+        );
+        assert!(gitea_netrc(gitea::Auth::Basic, credentials(None)).is_err());
+        assert_eq!(
+            gitea_netrc(gitea::Auth::Token, credentials(None)).unwrap(),
+            "hunter2"
+        );
+        assert_eq!(
+            gitea_netrc(gitea::Auth::Bearer, credentials(Some("shipit"))).unwrap(),
+            "hunter2"
+        );
     }
 }
@@ -0,0 +1,192 @@
+use std::{fmt, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// a provider token, which is never printed
+pub enum Token {
+    Static(String),
+    /// read again on every request, so that rotated tokens are picked up
+    File(PathBuf),
+}
+
+impl Token {
+    pub fn get(&self) -> Result<String> {
+        match self {
+            Token::Static(token) => Ok(token.clone()),
+            Token::File(path) => Ok(std::fs::read_to_string(path)
+                .with_context(|| format!("could not read token from {}", path.display()))?
+                .trim()
+                .to_string()),
+        }
+    }
+}
+
+impl From<&str> for Token {
+    fn from(token: &str) -> Self {
+        Token::Static(token.into())
+    }
+}
+
+impl From<String> for Token {
+    fn from(token: String) -> Self {
+        Token::Static(token)
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Static(_) => f.write_str("<redacted>"),
+            Token::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+/// where the token of a provider comes from, falling back to ~/.netrc when none is set
+#[derive(Default, Deserialize)]
+pub struct TokenSource {
+    pub token: Option<String>,
+    pub token_file: Option<PathBuf>,
+    pub token_env: Option<String>,
+}
+
+impl TokenSource {
+    pub fn is_empty(&self) -> bool {
+        self.token.is_none() && self.token_file.is_none() && self.token_env.is_none()
+    }
+
+    /// resolves the token, formatting netrc credentials for the provider
+    pub fn resolve(
+        self,
+        api_url: &str,
+        netrc: impl Fn(Credentials) -> Result<String>,
+    ) -> Result<Token> {
+        match (self.token, self.token_file, self.token_env) {
+            (Some(token), None, None) => Ok(Token::Static(token)),
+            (None, Some(path), None) => Ok(Token::File(path)),
+            (None, None, Some(name)) => {
+                std::env::var(&name).map(Token::Static).with_context(|| {
+                    format!("could not read token from environment variable {}", name)
+                })
+            }
+            (None, None, None) => netrc(netrc_credentials(api_url)?)
+                .map(Token::Static)
+                .context("invalid credentials in ~/.netrc"),
+            _ => bail!("only one of token, token_file and token_env can be set"),
+        }
+    }
+}
+
+impl fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSource")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("token_file", &self.token_file)
+            .field("token_env", &self.token_env)
+            .finish()
+    }
+}
+
+/// a login and password from ~/.netrc
+pub struct Credentials {
+    pub login: Option<String>,
+    pub password: String,
+}
+
+/// finds the ~/.netrc credentials for the host of api_url
+pub fn netrc_credentials(api_url: &str) -> Result<Credentials> {
+    let host = Url::parse(api_url)?
+        .host_str()
+        .ok_or_else(|| anyhow!("missing host in {}", api_url))?
+        .to_string();
+    let path = netrc_path().ok_or_else(|| anyhow!("could not locate ~/.netrc"))?;
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("missing token, and could not read {}", path.display()))?;
+    lookup(&content, &host).ok_or_else(|| anyhow!("missing token, and no credentials for {}", host))
+}
+
+fn netrc_path() -> Option<PathBuf> {
+    std::env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc")))
+}
+
+/// finds the credentials of a machine, or the default ones
+fn lookup(netrc: &str, host: &str) -> Option<Credentials> {
+    // machine name (None for the default entry), login and password
+    let mut entries: Vec<(Option<&str>, Option<&str>, Option<&str>)> = vec![];
+    let mut tokens = netrc.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match (token, entries.last_mut()) {
+            ("machine", _) => entries.push((tokens.next(), None, None)),
+            ("default", _) => entries.push((None, None, None)),
+            ("login", Some(entry)) => entry.1 = tokens.next(),
+            ("password", Some(entry)) => entry.2 = tokens.next(),
+            ("account", _) => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+
+    let credentials = |(_, login, password): &(Option<&str>, Option<&str>, Option<&str>)| {
+        Some(Credentials {
+            login: login.map(String::from),
+            password: (*password)?.to_string(),
+        })
+    };
+    entries
+        .iter()
+        .find(|entry| entry.0 == Some(host))
+        .or_else(|| entries.iter().find(|entry| entry.0.is_none()))
+        .and_then(credentials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let netrc = r#"
+machine gitlab.com login oauth2 password glpat-1
+machine gitea.example.com
+  login shipit
+  password hunter2
+default login anonymous password guest
+"#;
+
+        let credentials = lookup(netrc, "gitea.example.com").unwrap();
+        assert_eq!(credentials.login.as_deref(), Some("shipit"));
+        assert_eq!(credentials.password, "hunter2");
+
+        assert_eq!(lookup(netrc, "gitlab.com").unwrap().password, "glpat-1");
+        assert_eq!(lookup(netrc, "example.com").unwrap().password, "guest");
+        assert!(lookup("machine gitlab.com login oauth2", "gitlab.com").is_none());
+    }
+
+    #[test]
+    fn test_file_token_is_reread() {
+        let path = std::env::temp_dir().join(format!("shipit-token-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+
+        let token = Token::File(path.clone());
+        assert_eq!(token.get().unwrap(), "first");
+        std::fs::write(&path, "second\n").unwrap();
+        assert_eq!(token.get().unwrap(), "second");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_debug_redacts_tokens() {
+        let source = TokenSource {
+            token: Some("hunter2".into()),
+            ..Default::default()
+        };
+        assert!(!format!("{:?}", source).contains("hunter2"));
+        assert_eq!(format!("{:?}", Token::from("hunter2")), "<redacted>");
+    }
+}
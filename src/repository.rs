@@ -1,20 +1,65 @@
 use anyhow::Result;
 use bytes::Bytes;
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt,
+};
 
-use super::commit::CommitRequest;
+use super::{
+    commit::{CommitInfo, CommitRequest},
+    merge_request::{MergeRequest, MergeRequestInfo, MergeRequestState},
+    status::CommitStatus,
+    tag::{Release, Tag},
+};
+
+/// returned by Repository::commit when the files changed on the branch since they were read
+#[derive(Debug)]
+pub struct Conflict(pub String);
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "branch changed since files were read: {}", self.0)
+    }
+}
+
+impl std::error::Error for Conflict {}
 
 pub trait Repository {
+    fn branch_exists(&self, branch: &str) -> Result<bool>;
     fn get(&self, path: &str, reference: &str) -> Result<Bytes>;
-    fn commit(&mut self, payload: CommitRequest) -> Result<()>;
+    fn commit(&mut self, payload: CommitRequest) -> Result<CommitInfo>;
+    fn delete_branch(&mut self, branch: &str) -> Result<()>;
+    /// returns the merge request open from source_branch into target_branch, if any
+    fn find_merge_request(
+        &self,
+        source_branch: &str,
+        target_branch: &str,
+    ) -> Result<Option<MergeRequestInfo>>;
+    /// opens a merge request, or returns the one already open for the same branches
+    fn open_merge_request(&self, request: &MergeRequest) -> Result<MergeRequestInfo>;
+    /// merges the merge request as soon as its pipeline or checks succeed
+    fn enable_auto_merge(&self, merge_request: &MergeRequestInfo) -> Result<()>;
+    fn merge_request_state(&self, merge_request: &MergeRequestInfo) -> Result<MergeRequestState>;
+    fn create_tag(&self, tag: &Tag, sha: &str) -> Result<()>;
+    /// creates a release, returning its URL
+    fn create_release(&self, release: &Release) -> Result<String>;
+    fn set_commit_status(&self, sha: &str, status: &CommitStatus) -> Result<()>;
 }
 
 #[derive(Default)]
 pub struct InMemoryRepository {
     files: HashMap<String, Bytes>,
+    /// states returned by merge_request_state, one per call
+    pub merge_request_states: RefCell<VecDeque<MergeRequestState>>,
 }
 
 impl Repository for InMemoryRepository {
+    fn branch_exists(&self, branch: &str) -> Result<bool> {
+        let prefix = format!("{}/", branch);
+        Ok(self.files.keys().any(|key| key.starts_with(&prefix)))
+    }
+
     fn get(&self, path: &str, reference: &str) -> Result<Bytes> {
         let key = format!("{}/{}", reference, path);
         self.files
@@ -23,18 +68,90 @@ impl Repository for InMemoryRepository {
             .ok_or_else(|| anyhow::anyhow!("File not found"))
     }
 
-    fn commit(&mut self, payload: CommitRequest) -> Result<()> {
+    fn commit(&mut self, payload: CommitRequest) -> Result<CommitInfo> {
+        let files = payload.paths();
+        if let Some(start_branch) = &payload.start_branch {
+            // create the branch by copying the files of the one it starts from
+            let prefix = format!("{}/", start_branch);
+            let copied: Vec<(String, Bytes)> = self
+                .files
+                .iter()
+                .filter_map(|(key, content)| {
+                    let path = key.strip_prefix(&prefix)?;
+                    Some((format!("{}/{}", payload.branch, path), content.clone()))
+                })
+                .collect();
+            self.files.extend(copied);
+        }
+        for (to, from) in payload.moved.iter() {
+            let content = self
+                .files
+                .remove(&format!("{}/{}", payload.branch, from))
+                .ok_or_else(|| anyhow::anyhow!("File not found"))?;
+            self.files
+                .insert(format!("{}/{}", payload.branch, to), content);
+        }
+        for filename in payload.deleted.iter() {
+            self.files
+                .remove(&format!("{}/{}", payload.branch, filename));
+        }
         for (filename, content) in payload.files.iter() {
             let key = format!("{}/{}", payload.branch, filename);
             self.files.insert(key, content.clone());
         }
+        Ok(CommitInfo {
+            files,
+            ..Default::default()
+        })
+    }
+
+    fn delete_branch(&mut self, branch: &str) -> Result<()> {
+        let prefix = format!("{}/", branch);
+        self.files.retain(|key, _| !key.starts_with(&prefix));
         Ok(())
     }
+
+    fn find_merge_request(
+        &self,
+        _source_branch: &str,
+        _target_branch: &str,
+    ) -> Result<Option<MergeRequestInfo>> {
+        Ok(None)
+    }
+
+    fn open_merge_request(&self, _request: &MergeRequest) -> Result<MergeRequestInfo> {
+        Err(anyhow::anyhow!("Merge requests are not supported"))
+    }
+
+    fn enable_auto_merge(&self, _merge_request: &MergeRequestInfo) -> Result<()> {
+        Err(anyhow::anyhow!("Merge requests are not supported"))
+    }
+
+    fn merge_request_state(&self, _merge_request: &MergeRequestInfo) -> Result<MergeRequestState> {
+        self.merge_request_states
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("Merge requests are not supported"))
+    }
+
+    fn create_tag(&self, _tag: &Tag, _sha: &str) -> Result<()> {
+        Err(anyhow::anyhow!("Tags are not supported"))
+    }
+
+    fn create_release(&self, _release: &Release) -> Result<String> {
+        Err(anyhow::anyhow!("Releases are not supported"))
+    }
+
+    fn set_commit_status(&self, _sha: &str, _status: &CommitStatus) -> Result<()> {
+        Err(anyhow::anyhow!("Commit statuses are not supported"))
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::collections::HashSet;
+
     use super::*;
 
     #[test]
@@ -43,6 +160,7 @@ mod tests {
         let test_content = Bytes::from("Hello World");
         let mut repo = InMemoryRepository {
             files: HashMap::from([("test/file.txt".to_string(), test_content.clone())]),
+            ..Default::default()
         };
 
         // try getting the file
@@ -63,5 +181,38 @@ mod tests {
             repo.get(other_file_name, "test").unwrap(),
             &other_test_content
         );
+
+        // try moving a file and deleting the other one
+        repo.commit(CommitRequest {
+            moved: HashMap::from([("moved.txt".to_string(), "file.txt".to_string())]),
+            deleted: HashSet::from([other_file_name.to_string()]),
+            branch: "test".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(repo.get("moved.txt", "test").unwrap(), &test_content);
+        assert!(repo.get("file.txt", "test").is_err());
+        assert!(repo.get(other_file_name, "test").is_err());
+
+        // try creating a new branch
+        assert!(!repo.branch_exists("feature").unwrap());
+        repo.commit(CommitRequest {
+            files: HashMap::from([("feature.txt".to_string(), test_content.clone())]),
+            branch: "feature".to_string(),
+            start_branch: Some("test".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(repo.branch_exists("feature").unwrap());
+        assert_eq!(repo.get("moved.txt", "feature").unwrap(), &test_content);
+        assert_eq!(repo.get("feature.txt", "feature").unwrap(), &test_content);
+
+        // try deleting it again
+        repo.delete_branch("feature").unwrap();
+        assert!(!repo.branch_exists("feature").unwrap());
+        assert!(repo.branch_exists("test").unwrap());
+        assert!(repo.get("feature.txt", "test").is_err());
     }
 }
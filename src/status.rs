@@ -0,0 +1,33 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Pending,
+    Success,
+    Failure,
+}
+
+impl FromStr for State {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "success" => Ok(Self::Success),
+            "failure" => Ok(Self::Failure),
+            _ => Err(anyhow!("unknown commit status {s}")),
+        }
+    }
+}
+
+/// a status to report on a commit, shown by providers next to it
+#[derive(Clone, Debug)]
+pub struct CommitStatus {
+    pub state: State,
+    /// distinguishes this status from the others set on the same commit
+    pub context: String,
+    pub target_url: Option<String>,
+    pub description: Option<String>,
+}
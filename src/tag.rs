@@ -0,0 +1,15 @@
+/// a tag to create on the resulting commit
+#[derive(Clone, Debug, Default)]
+pub struct Tag {
+    pub name: String,
+    /// creates an annotated tag when set, a lightweight one otherwise
+    pub message: Option<String>,
+}
+
+/// a release for an existing tag
+#[derive(Clone, Debug, Default)]
+pub struct Release {
+    pub tag: String,
+    pub name: String,
+    pub notes: String,
+}
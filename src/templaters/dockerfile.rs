@@ -0,0 +1,491 @@
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// an instruction as seen by the Docker parser, possibly spanning multiple lines
+struct Instruction {
+    keyword: String,
+    /// arguments of the instruction, with line continuations removed
+    args: String,
+    /// (line, offset) in the original file of each byte in `args`
+    positions: Vec<(usize, usize)>,
+}
+
+impl Instruction {
+    /// returns the line and byte range in the original file covered by args[start..end]
+    fn locate(&self, start: usize, end: usize) -> Result<(usize, usize, usize)> {
+        if start == end {
+            let (line, offset) = match self.positions.get(start) {
+                Some(position) => *position,
+                None => {
+                    let (line, offset) = self.positions[start - 1];
+                    (line, offset + 1)
+                }
+            };
+            return Ok((line, offset, offset));
+        }
+
+        let (line, from) = self.positions[start];
+        let (end_line, to) = self.positions[end - 1];
+        if line != end_line {
+            bail!(
+                "cannot edit {} value spanning multiple lines (line {})",
+                self.keyword,
+                line + 1
+            );
+        }
+        Ok((line, from, to + 1))
+    }
+}
+
+/// a replacement of the original file's line[from..to]
+struct Edit {
+    line: usize,
+    from: usize,
+    to: usize,
+    value: String,
+}
+
+/// returns the escape character selected by the `# escape=` parser directive
+fn escape_char(lines: &[String]) -> char {
+    for line in lines {
+        let Some(directive) = line.trim().strip_prefix('#') else {
+            break;
+        };
+        let Some((key, value)) = directive.split_once('=') else {
+            break;
+        };
+        if key.trim().eq_ignore_ascii_case("escape") {
+            if value.trim() == "`" {
+                return '`';
+            }
+            break;
+        }
+    }
+    '\\'
+}
+
+fn parse(lines: &[String], escape: char) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut current: Option<Instruction> = None;
+
+    for (number, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        // comments and empty lines are ignored, even in the middle of a continuation
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let (mut instruction, start) = match current.take() {
+            Some(instruction) => (instruction, 0),
+            None => {
+                let leading = line.len() - line.trim_start().len();
+                let keyword_end = line[leading..]
+                    .find(char::is_whitespace)
+                    .map_or(line.len(), |end| leading + end);
+                let instruction = Instruction {
+                    keyword: line[leading..keyword_end].to_ascii_uppercase(),
+                    args: String::new(),
+                    positions: vec![],
+                };
+                (instruction, keyword_end)
+            }
+        };
+
+        let body = line[start..].trim_end();
+        let (body, continued) = match body.strip_suffix(escape) {
+            Some(body) => (body, true),
+            None => (body, false),
+        };
+        instruction.args.push_str(body);
+        instruction
+            .positions
+            .extend((0..body.len()).map(|offset| (number, start + offset)));
+
+        if continued {
+            current = Some(instruction);
+        } else {
+            instructions.push(instruction);
+        }
+    }
+
+    instructions.extend(current);
+    instructions
+}
+
+/// returns the byte ranges of the whitespace-separated words in text, honoring quotes and escapes
+fn words(text: &str, escape: char) -> Vec<(usize, usize)> {
+    let mut words = vec![];
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        if c == escape {
+            escaped = true;
+            start.get_or_insert(index);
+            continue;
+        }
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                start.get_or_insert(index);
+            }
+            None if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    words.push((start, index));
+                }
+            }
+            None => {
+                start.get_or_insert(index);
+            }
+        }
+    }
+
+    if let Some(start) = start {
+        words.push((start, text.len()));
+    }
+    words
+}
+
+/// quotes value if it would otherwise be split into multiple words
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return value.into();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// splits an image reference into its `name[:tag][@digest]` components
+fn split_image(reference: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (rest, digest) = match reference.split_once('@') {
+        Some((rest, digest)) => (rest, Some(digest)),
+        None => (reference, None),
+    };
+    // a colon followed by a slash belongs to the registry port, not to the tag
+    match rest.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, Some(tag), digest),
+        _ => (rest, None, digest),
+    }
+}
+
+/// changes a single component of an image reference (`name[:tag][@digest]`)
+fn set_image_field(reference: &str, field: Option<&str>, value: &str) -> Result<String> {
+    let (name, tag, digest) = split_image(reference);
+    let set = Some(value).filter(|value| !value.is_empty());
+
+    let (name, tag, digest) = match field {
+        None => return Ok(value.into()),
+        Some("image") => (value, tag, digest),
+        Some("tag") => (name, set, digest),
+        Some("digest") => (name, tag, set),
+        Some(other) => bail!("unknown image field {}", other),
+    };
+
+    let mut reference = name.to_string();
+    if let Some(tag) = tag {
+        reference = format!("{reference}:{tag}");
+    }
+    if let Some(digest) = digest {
+        reference = format!("{reference}@{digest}");
+    }
+    Ok(reference)
+}
+
+fn from_edits(
+    instructions: &[Instruction],
+    stage: &str,
+    field: Option<&str>,
+    value: &str,
+    escape: char,
+) -> Result<Vec<Edit>> {
+    let stages = instructions.iter().filter(|i| i.keyword == "FROM");
+    for (index, instruction) in stages.enumerate() {
+        let words = words(&instruction.args, escape);
+        let mut words = words
+            .iter()
+            .map(|&(start, end)| (start, end, &instruction.args[start..end]))
+            .skip_while(|(_, _, word)| word.starts_with("--"));
+
+        let Some((start, end, image)) = words.next() else {
+            continue;
+        };
+        let name = match (words.next(), words.next()) {
+            (Some((_, _, keyword)), Some((_, _, name))) if keyword.eq_ignore_ascii_case("as") => {
+                Some(name)
+            }
+            _ => None,
+        };
+
+        let matches = name.map_or(false, |name| name.eq_ignore_ascii_case(stage))
+            || index.to_string() == stage;
+        if !matches {
+            continue;
+        }
+
+        let (line, from, to) = instruction.locate(start, end)?;
+        return Ok(vec![Edit {
+            line,
+            from,
+            to,
+            value: set_image_field(image, field, value)?,
+        }]);
+    }
+
+    Err(anyhow!("could not find stage {}", stage))
+}
+
+/// edits every `NAME=value` declaration of the given ARG or ENV name
+fn variable_edits(
+    instructions: &[Instruction],
+    keyword: &str,
+    name: &str,
+    value: &str,
+    escape: char,
+) -> Result<Vec<Edit>> {
+    let mut edits = vec![];
+
+    for instruction in instructions.iter().filter(|i| i.keyword == keyword) {
+        let words = words(&instruction.args, escape);
+        let Some(&(first_start, first_end)) = words.first() else {
+            continue;
+        };
+
+        // legacy `ENV NAME value with spaces` form
+        if keyword == "ENV" && !instruction.args[first_start..first_end].contains('=') {
+            if &instruction.args[first_start..first_end] != name {
+                continue;
+            }
+            let (start, end) = match (words.get(1), words.last()) {
+                (Some(&(start, _)), Some(&(_, end))) => (start, end),
+                _ => bail!("missing value for ENV {}", name),
+            };
+            let (line, from, to) = instruction.locate(start, end)?;
+            edits.push(Edit {
+                line,
+                from,
+                to,
+                value: value.into(),
+            });
+            continue;
+        }
+
+        for &(start, end) in &words {
+            let word = &instruction.args[start..end];
+            let (declared, start, replacement) = match word.split_once('=') {
+                Some((declared, _)) => (declared, start + declared.len() + 1, quote(value)),
+                // ARG without a default value
+                None => (word, end, format!("={}", quote(value))),
+            };
+            if declared != name {
+                continue;
+            }
+            let (line, from, to) = instruction.locate(start, end)?;
+            edits.push(Edit {
+                line,
+                from,
+                to,
+                value: replacement,
+            });
+        }
+    }
+
+    if edits.is_empty() {
+        bail!("could not find {} {}", keyword, name);
+    }
+    Ok(edits)
+}
+
+/// computes the edits needed to apply the change at the given path
+fn edits(instructions: &[Instruction], path: &str, value: &str, escape: char) -> Result<Vec<Edit>> {
+    let (kind, target) = path
+        .split_once('.')
+        .ok_or_else(|| anyhow!("invalid Dockerfile path {}", path))?;
+
+    match kind {
+        "from" => {
+            // stage names may contain dots, so the field is only split off if it's a known one
+            let (stage, field) = match target.rsplit_once('.') {
+                Some((stage, field @ ("image" | "tag" | "digest"))) => (stage, Some(field)),
+                _ => (target, None),
+            };
+            from_edits(instructions, stage, field, value, escape)
+        }
+        "arg" => variable_edits(instructions, "ARG", target, value, escape),
+        "env" => variable_edits(instructions, "ENV", target, value, escape),
+        _ => Err(anyhow!("invalid Dockerfile path {}", path)),
+    }
+}
+
+/// returns the current value at the given path, if any
+pub fn value(file: &Bytes, path: &str) -> Result<Option<String>> {
+    let lines: Vec<String> = std::str::from_utf8(file)?
+        .split('\n')
+        .map(String::from)
+        .collect();
+    let escape = escape_char(&lines);
+
+    let Some(edit) = edits(&parse(&lines, escape), path, "", escape)?.into_iter().next() else {
+        return Ok(None);
+    };
+    let current = &lines[edit.line][edit.from..edit.to];
+
+    let field = path
+        .strip_prefix("from.")
+        .and_then(|target| target.rsplit_once('.'))
+        .map(|(_, field)| field);
+    let value = match field {
+        Some("image") => Some(split_image(current).0),
+        Some("tag") => split_image(current).1,
+        Some("digest") => split_image(current).2,
+        _ => Some(current),
+    };
+    Ok(value.filter(|value| !value.is_empty()).map(|value| {
+        match value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+        {
+            Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+            None => value.into(),
+        }
+    }))
+}
+
+pub fn update_file(file: &Bytes, changes: &HashMap<String, String>) -> Result<Bytes> {
+    let mut lines: Vec<String> = std::str::from_utf8(file)?
+        .split('\n')
+        .map(String::from)
+        .collect();
+    let escape = escape_char(&lines);
+
+    // apply changes
+    for (path, value) in changes {
+        let mut edits = edits(&parse(&lines, escape), path, value, escape)?;
+        // apply from the end so that earlier offsets stay valid
+        edits.sort_by(|a, b| (b.line, b.from).cmp(&(a.line, a.from)));
+        for edit in edits {
+            lines[edit.line].replace_range(edit.from..edit.to, &edit.value);
+        }
+    }
+
+    Ok(Bytes::from(lines.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(original: &str, changes: &[(&str, &str)]) -> Result<String> {
+        let changes = changes
+            .iter()
+            .map(|(path, value)| (path.to_string(), value.to_string()))
+            .collect();
+        let changed = update_file(&Bytes::from(original.to_string()), &changes)?;
+        Ok(String::from_utf8(changed.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_update_file() {
+        let original = r#"# syntax=docker/dockerfile:1
+ARG RUST_VERSION=1.70
+FROM --platform=$BUILDPLATFORM rust:${RUST_VERSION}-slim AS builder
+# the version is injected at build time
+ARG VERSION
+ENV APP_ENV=staging \
+    # keep logs quiet
+    RUST_LOG=info
+RUN cargo build --release
+
+FROM registry.example.com:5000/base/runtime:1.0@sha256:abcd as runtime
+ENV GREETING hello world
+COPY --from=builder /app /app"#;
+
+        let changed = update(
+            original,
+            &[
+                ("arg.RUST_VERSION", "1.71"),
+                ("arg.VERSION", "2.0.0"),
+                ("env.RUST_LOG", "debug"),
+                ("env.GREETING", "hi there"),
+                ("from.runtime.tag", "2.0"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            changed,
+            r#"# syntax=docker/dockerfile:1
+ARG RUST_VERSION=1.71
+FROM --platform=$BUILDPLATFORM rust:${RUST_VERSION}-slim AS builder
+# the version is injected at build time
+ARG VERSION=2.0.0
+ENV APP_ENV=staging \
+    # keep logs quiet
+    RUST_LOG=debug
+RUN cargo build --release
+
+FROM registry.example.com:5000/base/runtime:2.0@sha256:abcd as runtime
+ENV GREETING hi there
+COPY --from=builder /app /app"#
+        );
+    }
+
+    #[test]
+    fn test_update_image() {
+        let original = "FROM alpine:3.17\nFROM \\\n  golang:1.20 AS build\n";
+
+        assert_eq!(
+            update(original, &[("from.0", "alpine:3.18")]).unwrap(),
+            "FROM alpine:3.18\nFROM \\\n  golang:1.20 AS build\n"
+        );
+        assert_eq!(
+            update(original, &[("from.build.digest", "sha256:1234")]).unwrap(),
+            "FROM alpine:3.17\nFROM \\\n  golang:1.20@sha256:1234 AS build\n"
+        );
+        assert_eq!(
+            update(original, &[("env.CGO_ENABLED", "0")])
+                .unwrap_err()
+                .to_string(),
+            "could not find ENV CGO_ENABLED"
+        );
+        assert_eq!(
+            update(original, &[("from.missing.tag", "1")])
+                .unwrap_err()
+                .to_string(),
+            "could not find stage missing"
+        );
+    }
+
+    #[test]
+    fn test_value() {
+        let file = Bytes::from(
+            "ARG VERSION\nARG BASE=\"alpine 3\"\nFROM registry:5000/app:1.0@sha256:abcd AS app\n",
+        );
+
+        assert_eq!(value(&file, "arg.VERSION").unwrap(), None);
+        assert_eq!(value(&file, "arg.BASE").unwrap(), Some("alpine 3".into()));
+        assert_eq!(
+            value(&file, "from.app").unwrap(),
+            Some("registry:5000/app:1.0@sha256:abcd".into())
+        );
+        assert_eq!(
+            value(&file, "from.app.image").unwrap(),
+            Some("registry:5000/app".into())
+        );
+        assert_eq!(value(&file, "from.0.tag").unwrap(), Some("1.0".into()));
+        assert_eq!(
+            value(&file, "from.app.digest").unwrap(),
+            Some("sha256:abcd".into())
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine};
+use bytes::Bytes;
+use serde::Deserialize;
+
+/// where the content of a file comes from
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// inline text content
+    Content(String),
+    /// inline binary content, base64-encoded
+    Base64(String),
+    /// a file on the local filesystem
+    Path(PathBuf),
+}
+
+impl Source {
+    pub fn load(&self) -> Result<Bytes> {
+        Ok(match self {
+            Source::Content(content) => Bytes::from(content.clone()),
+            Source::Base64(content) => Bytes::from(general_purpose::STANDARD.decode(content)?),
+            Source::Path(path) => Bytes::from(
+                std::fs::read(path)
+                    .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))?,
+            ),
+        })
+    }
+}
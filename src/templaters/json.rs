@@ -2,7 +2,8 @@ use std::collections::HashMap;
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value};
 
 /// returns the value at the given path
 fn patch(base: &mut Value, path: &str, value: &str) -> Result<()> {
@@ -25,15 +26,64 @@ fn patch(base: &mut Value, path: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// returns the scalar value at the given path, if any
+pub fn value(file: &Bytes, path: &str) -> Result<Option<String>> {
+    let parsed: Value = serde_json::from_slice(file)?;
+    let mut current = &parsed;
+    for part in path.split('/') {
+        let next = match part.parse::<usize>() {
+            Ok(numeric_part) => current.get(numeric_part),
+            Err(_) => current.get(part),
+        };
+        let Some(next) = next else {
+            return Ok(None);
+        };
+        current = next;
+    }
+    Ok(match current {
+        Value::String(value) => Some(value.clone()),
+        Value::Array(_) | Value::Object(_) | Value::Null => None,
+        other => Some(other.to_string()),
+    })
+}
+
+/// serializes value the way file is formatted: compact, or pretty with the same indentation
+fn serialize(file: &Bytes, value: &Value) -> Result<Bytes> {
+    let text = String::from_utf8_lossy(file);
+    let indent = text
+        .lines()
+        .nth(1)
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .filter(|indent| !indent.is_empty());
+
+    let mut output = vec![];
+    match indent {
+        Some(indent) => value.serialize(&mut Serializer::with_formatter(
+            &mut output,
+            PrettyFormatter::with_indent(indent.as_bytes()),
+        ))?,
+        None => serde_json::to_writer(&mut output, value)?,
+    }
+    if text.ends_with('\n') {
+        output.push(b'\n');
+    }
+    Ok(Bytes::from(output))
+}
+
 pub fn update_file(file: &Bytes, changes: &HashMap<String, String>) -> Result<Bytes> {
-    let mut parsed = serde_json::from_slice(file)?;
+    let original: Value = serde_json::from_slice(file)?;
 
     // apply changes
+    let mut parsed = original.clone();
     for (path, value) in changes {
         patch(&mut parsed, path, value)?;
     }
 
-    Ok(Bytes::from(serde_json::to_vec(&parsed)?))
+    // keep the file as it is, so that unchanged files aren't committed
+    if parsed == original {
+        return Ok(file.clone());
+    }
+    serialize(file, &parsed)
 }
 
 #[cfg(test)]
@@ -60,4 +110,38 @@ mod tests {
         assert_eq!(parsed["test"]["nested"], "changed");
         assert_eq!(parsed["array"][0], "changed");
     }
+
+    #[test]
+    fn test_update_file_keeps_formatting() {
+        let original =
+            Bytes::from("{\n  \"name\": \"app\",\n  \"image\": {\n    \"tag\": \"1.0\"\n  }\n}\n");
+
+        let changes = HashMap::from([("image/tag".to_string(), "2.0".to_string())]);
+        assert_eq!(
+            update_file(&original, &changes).unwrap(),
+            Bytes::from("{\n  \"name\": \"app\",\n  \"image\": {\n    \"tag\": \"2.0\"\n  }\n}\n")
+        );
+
+        // no-op changes leave the file untouched
+        let changes = HashMap::from([("image/tag".to_string(), "1.0".to_string())]);
+        assert_eq!(update_file(&original, &changes).unwrap(), original);
+
+        let tabs = Bytes::from("{\n\t\"tag\": \"1.0\"\n}");
+        let changes = HashMap::from([("tag".to_string(), "2.0".to_string())]);
+        assert_eq!(
+            update_file(&tabs, &changes).unwrap(),
+            Bytes::from("{\n\t\"tag\": \"2.0\"\n}")
+        );
+    }
+
+    #[test]
+    fn test_value() {
+        let file = Bytes::from(r#"{"test": {"nested": "dummy", "number": 1}, "array": ["item"]}"#);
+
+        assert_eq!(value(&file, "test/nested").unwrap(), Some("dummy".into()));
+        assert_eq!(value(&file, "test/number").unwrap(), Some("1".into()));
+        assert_eq!(value(&file, "array/0").unwrap(), Some("item".into()));
+        assert_eq!(value(&file, "test").unwrap(), None);
+        assert_eq!(value(&file, "missing").unwrap(), None);
+    }
 }
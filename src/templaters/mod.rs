@@ -1,11 +1,20 @@
 use std::collections::HashMap;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
 use serde::Deserialize;
 
-use crate::{commit::FileList, repository::Repository};
+use crate::{
+    commit::{Changeset, FileList, ValueChange},
+    repository::Repository,
+};
 
+mod dockerfile;
+mod file;
 mod json;
+mod render;
+mod secret;
+mod sops;
 mod yaml;
 
 #[derive(Debug, Deserialize)]
@@ -19,30 +28,627 @@ pub enum Mutation {
         file: String,
         changes: HashMap<String, String>,
     },
+    Dockerfile {
+        file: String,
+        changes: HashMap<String, String>,
+    },
+    KubernetesSecret {
+        file: String,
+        changes: secret::SecretChanges,
+    },
+    SopsJson {
+        file: String,
+        changes: secret::SecretChanges,
+    },
+    SopsYaml {
+        file: String,
+        changes: secret::SecretChanges,
+    },
+    /// creates or replaces a file with the given content
+    File {
+        file: String,
+        #[serde(flatten)]
+        source: file::Source,
+    },
+    Delete {
+        file: String,
+    },
+    Move {
+        from: String,
+        to: String,
+    },
+    /// creates or replaces a file with the result of rendering a template
+    Render {
+        template: render::Template,
+        file: String,
+        #[serde(default)]
+        vars: HashMap<String, serde_json::Value>,
+    },
+}
+
+/// checks that every key is a valid path, splitting it on separator
+fn check_paths<'a>(
+    keys: impl IntoIterator<Item = &'a String>,
+    separator: &str,
+    check: impl Fn(&str) -> Result<()>,
+) -> Result<()> {
+    let mut empty = true;
+    for key in keys {
+        empty = false;
+        if key.split(separator).any(str::is_empty) {
+            bail!("invalid path {}", key);
+        }
+        check(key)?;
+    }
+    if empty {
+        bail!("no changes");
+    }
+    Ok(())
+}
+
+impl Mutation {
+    /// checks what can be checked without reading the repository
+    pub fn validate(&self) -> Result<()> {
+        let any = |_: &str| Ok(());
+        match self {
+            Mutation::Json { file, changes } => {
+                check_file(file)?;
+                check_paths(changes.keys(), "/", any)
+            }
+            Mutation::Yaml { file, changes } => {
+                check_file(file)?;
+                check_paths(changes.keys(), ".", any)
+            }
+            Mutation::Dockerfile { file, changes } => {
+                check_file(file)?;
+                check_paths(changes.keys(), ".", |key| match key.split_once('.') {
+                    Some(("from" | "arg" | "env", _)) => Ok(()),
+                    _ => bail!(
+                        "invalid Dockerfile path {}, expected from.*, arg.* or env.*",
+                        key
+                    ),
+                })
+            }
+            Mutation::KubernetesSecret { file, changes } => {
+                check_file(file)?;
+                check_paths(changes.0.keys(), ".", |key| match key.split_once('.') {
+                    Some(("data" | "stringData", _)) => Ok(()),
+                    _ => bail!(
+                        "invalid Secret path {}, expected data.* or stringData.*",
+                        key
+                    ),
+                })
+            }
+            Mutation::SopsJson { file, changes } => {
+                check_file(file)?;
+                check_paths(changes.0.keys(), "/", any)
+            }
+            Mutation::SopsYaml { file, changes } => {
+                check_file(file)?;
+                check_paths(changes.0.keys(), ".", any)
+            }
+            Mutation::File { file, source } => {
+                check_file(file)?;
+                source.load().map(|_| ())
+            }
+            Mutation::Delete { file } => check_file(file),
+            Mutation::Move { from, to } => {
+                check_file(from)?;
+                check_file(to)?;
+                if from == to {
+                    bail!("cannot move {} onto itself", from);
+                }
+                Ok(())
+            }
+            Mutation::Render { template, file, .. } => {
+                check_file(file)?;
+                match template {
+                    render::Template::Path(path) => {
+                        render::check(&file::Source::Path(path.clone()).load()?)
+                    }
+                    // read from the repository, so it can only be checked when committing
+                    render::Template::Repository(path) => check_file(path),
+                }
+            }
+        }
+    }
+}
+
+fn check_file(file: &str) -> Result<()> {
+    if file.is_empty() || file.starts_with('/') || file.split('/').any(|part| part == "..") {
+        bail!("invalid file path '{}'", file);
+    }
+    Ok(())
+}
+
+/// returns the scalar value at path in a file handled by the given templater
+pub fn value(templater: &str, content: &Bytes, path: &str) -> Result<Option<String>> {
+    match templater {
+        "json" => json::value(content, path),
+        "yaml" => yaml::value(content, path),
+        "dockerfile" => dockerfile::value(content, path),
+        other => bail!("templater {} cannot read values", other),
+    }
+}
+
+/// guesses the templater able to read a file from its name
+pub fn templater_for(file: &str) -> Option<&'static str> {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    if name.ends_with(".json") {
+        Some("json")
+    } else if name.ends_with(".yaml") || name.ends_with(".yml") {
+        Some("yaml")
+    } else if name == "Dockerfile"
+        || name.starts_with("Dockerfile.")
+        || name.ends_with(".Dockerfile")
+    {
+        Some("dockerfile")
+    } else {
+        None
+    }
+}
+
+/// parses `templater:file:path=value` assignments, grouping the ones for the same file
+pub fn from_assignments(assignments: &[String]) -> Result<Vec<Mutation>> {
+    let mut grouped: Vec<(String, String, HashMap<String, String>)> = vec![];
+    for assignment in assignments {
+        let invalid = || {
+            anyhow!(
+                "invalid assignment {}, expected templater:file:path=value",
+                assignment
+            )
+        };
+        let (target, value) = assignment.split_once('=').ok_or_else(invalid)?;
+        let (templater, target) = target.split_once(':').ok_or_else(invalid)?;
+        let (file, path) = target.rsplit_once(':').ok_or_else(invalid)?;
+        if file.is_empty() || path.is_empty() {
+            return Err(invalid());
+        }
+
+        let templater = templater.replace('-', "_");
+        match grouped
+            .iter_mut()
+            .find(|(other, other_file, _)| *other == templater && other_file == file)
+        {
+            Some((_, _, changes)) => {
+                changes.insert(path.into(), value.into());
+            }
+            None => grouped.push((
+                templater,
+                file.into(),
+                HashMap::from([(path.into(), value.into())]),
+            )),
+        }
+    }
+
+    grouped
+        .into_iter()
+        .map(|(templater, file, changes)| {
+            Ok(match templater.as_str() {
+                "json" => Mutation::Json { file, changes },
+                "yaml" => Mutation::Yaml { file, changes },
+                "dockerfile" => Mutation::Dockerfile { file, changes },
+                "kubernetes_secret" => Mutation::KubernetesSecret {
+                    file,
+                    changes: secret::SecretChanges(changes),
+                },
+                "sops_json" => Mutation::SopsJson {
+                    file,
+                    changes: secret::SecretChanges(changes),
+                },
+                "sops_yaml" => Mutation::SopsYaml {
+                    file,
+                    changes: secret::SecretChanges(changes),
+                },
+                other => bail!("templater {} doesn't support assignments", other),
+            })
+        })
+        .collect()
+}
+
+/// returns the content of file, taking into account the changes made by previous mutations
+fn fetch(
+    repository: &dyn Repository,
+    branch: &str,
+    changed: &Changeset,
+    originals: &mut FileList,
+    file: &str,
+) -> Result<Bytes> {
+    if let Some(content) = changed.files.get(file) {
+        return Ok(content.clone());
+    }
+    if changed.deleted.contains(file) {
+        return Err(anyhow!("file {} has been deleted", file));
+    }
+    let file = changed.moved.get(file).map_or(file, String::as_str);
+    if let Some(content) = originals.get(file) {
+        return Ok(content.clone());
+    }
+    let content = repository.get(file, branch)?;
+    originals.insert(file.into(), content.clone());
+    Ok(content)
+}
+
+/// whether file was written by a previous mutation without existing in the repository,
+/// in which case deleting or moving it only concerns the changeset
+fn created(
+    repository: &dyn Repository,
+    branch: &str,
+    changed: &Changeset,
+    originals: &FileList,
+    file: &str,
+) -> bool {
+    changed.files.contains_key(file)
+        && !changed.moved.contains_key(file)
+        && !originals.contains_key(file)
+        && repository.get(file, branch).is_err()
+}
+
+/// records the values about to be changed in file, skipping those that stay the same
+fn record_values(
+    changed: &mut Changeset,
+    file: &str,
+    content: &Bytes,
+    changes: &HashMap<String, String>,
+    value: fn(&Bytes, &str) -> Result<Option<String>>,
+) {
+    let mut keys: Vec<&String> = changes.keys().collect();
+    keys.sort();
+    for key in keys {
+        let old = value(content, key).ok().flatten();
+        if old.as_ref() == changes.get(key) {
+            continue;
+        }
+        changed.values.push(ValueChange {
+            file: file.into(),
+            key: key.clone(),
+            old,
+            new: changes[key].clone(),
+        });
+    }
 }
 
 pub fn mutate(
     repository: &dyn Repository,
     branch: &str,
     mutations: &[Mutation],
-) -> Result<FileList> {
-    let mut changed: std::collections::HashMap<String, bytes::Bytes> = FileList::default();
+) -> Result<Changeset> {
+    let mut changed = Changeset::default();
+    let mut originals = FileList::default();
     for mutation in mutations {
-        let delta = match mutation {
+        let (file, patched) = match mutation {
             Mutation::Json { file, changes } => {
-                let to_patch = repository.get(file, branch)?;
+                let to_patch = fetch(repository, branch, &changed, &mut originals, file)?;
                 log::debug!("patching JSON file file={file} branch={branch}");
-                let patched = json::update_file(&to_patch, changes)?;
-                FileList::from([(file.into(), patched)])
+                record_values(&mut changed, file, &to_patch, changes, json::value);
+                (file, json::update_file(&to_patch, changes)?)
             }
             Mutation::Yaml { file, changes } => {
-                let to_patch = repository.get(file, branch)?;
+                let to_patch = fetch(repository, branch, &changed, &mut originals, file)?;
                 log::debug!("patching YAML file file={file} branch={branch}");
-                let patched = yaml::update_file(&to_patch, changes)?;
-                FileList::from([(file.into(), patched)])
+                record_values(&mut changed, file, &to_patch, changes, yaml::value);
+                (file, yaml::update_file(&to_patch, changes)?)
+            }
+            Mutation::Dockerfile { file, changes } => {
+                let to_patch = fetch(repository, branch, &changed, &mut originals, file)?;
+                log::debug!("patching Dockerfile file={file} branch={branch}");
+                record_values(&mut changed, file, &to_patch, changes, dockerfile::value);
+                (file, dockerfile::update_file(&to_patch, changes)?)
+            }
+            Mutation::KubernetesSecret { file, changes } => {
+                let to_patch = fetch(repository, branch, &changed, &mut originals, file)?;
+                log::debug!("patching Kubernetes Secret file={file} branch={branch}");
+                (file, secret::update_file(&to_patch, changes)?)
+            }
+            Mutation::SopsJson { file, changes } => {
+                let to_patch = fetch(repository, branch, &changed, &mut originals, file)?;
+                log::debug!("patching SOPS-encrypted JSON file file={file} branch={branch}");
+                let patched = sops::update_file(
+                    &to_patch,
+                    sops::Format::Json,
+                    changes,
+                    &sops::identities()?,
+                )?;
+                (file, patched)
+            }
+            Mutation::SopsYaml { file, changes } => {
+                let to_patch = fetch(repository, branch, &changed, &mut originals, file)?;
+                log::debug!("patching SOPS-encrypted YAML file file={file} branch={branch}");
+                let patched = sops::update_file(
+                    &to_patch,
+                    sops::Format::Yaml,
+                    changes,
+                    &sops::identities()?,
+                )?;
+                (file, patched)
+            }
+            Mutation::File { file, source } => {
+                log::debug!("writing file file={file} branch={branch}");
+                changed.deleted.remove(file);
+                (file, source.load()?)
+            }
+            Mutation::Render {
+                template,
+                file,
+                vars,
+            } => {
+                let template = match template {
+                    render::Template::Path(path) => file::Source::Path(path.clone()).load()?,
+                    render::Template::Repository(path) => {
+                        fetch(repository, branch, &changed, &mut originals, path)?
+                    }
+                };
+                log::debug!("rendering template file={file} branch={branch}");
+                changed.deleted.remove(file);
+                (file, render::render(&template, vars)?)
+            }
+            Mutation::Delete { file } => {
+                log::debug!("deleting file file={file} branch={branch}");
+                let created = created(repository, branch, &changed, &originals, file);
+                changed.files.remove(file);
+                if created {
+                    continue;
+                }
+                // deleting a moved file removes the original one
+                let file = changed.moved.remove(file).unwrap_or_else(|| file.into());
+                changed.deleted.insert(file);
+                continue;
+            }
+            Mutation::Move { from, to } => {
+                log::debug!("moving file from={from} to={to} branch={branch}");
+                let created = created(repository, branch, &changed, &originals, from);
+                if let Some(content) = changed.files.remove(from) {
+                    changed.files.insert(to.into(), content);
+                }
+                if created {
+                    continue;
+                }
+                let from = changed.moved.remove(from).unwrap_or_else(|| from.into());
+                changed.moved.insert(to.into(), from);
+                continue;
             }
         };
-        changed.extend(delta);
+        changed.files.insert(file.into(), patched);
     }
+
+    // drop files whose content didn't change, so that reruns don't produce empty commits
+    changed.files.retain(|file, content| {
+        let original = changed.moved.get(file).unwrap_or(file);
+        let unchanged = match originals.get(original) {
+            Some(original) => original == content,
+            // whole-file mutations don't read the file, and it might not exist yet
+            None => repository
+                .get(original, branch)
+                .map_or(false, |original| original == *content),
+        };
+        if unchanged {
+            log::debug!("file unchanged, skipping file={file} branch={branch}");
+        }
+        !unchanged
+    });
+    changed
+        .values
+        .retain(|value| changed.files.contains_key(&value.file));
+
     Ok(changed)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{commit::CommitRequest, repository::InMemoryRepository};
+
+    use super::*;
+
+    #[test]
+    fn test_mutate() {
+        let mut repo = InMemoryRepository::default();
+        repo.commit(CommitRequest {
+            branch: "main".into(),
+            files: HashMap::from([
+                ("app.json".to_string(), Bytes::from(r#"{"version":"1"}"#)),
+                ("old.txt".to_string(), Bytes::from("old")),
+            ]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let changed = mutate(
+            &repo,
+            "main",
+            &serde_json::from_str::<Vec<Mutation>>(
+                r#"[
+                    {"templater": "move", "from": "app.json", "to": "config/app.json"},
+                    {"templater": "json", "file": "config/app.json", "changes": {"version": "2"}},
+                    {"templater": "file", "file": "new.txt", "content": "hello"},
+                    {"templater": "file", "file": "new.bin", "base64": "BNI="},
+                    {"templater": "delete", "file": "old.txt"}
+                ]"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            changed.files,
+            HashMap::from([
+                (
+                    "config/app.json".to_string(),
+                    Bytes::from(r#"{"version":"2"}"#)
+                ),
+                ("new.txt".to_string(), Bytes::from("hello")),
+                ("new.bin".to_string(), Bytes::from(vec![4, 210])),
+            ])
+        );
+        assert_eq!(
+            changed.moved,
+            HashMap::from([("config/app.json".to_string(), "app.json".to_string())])
+        );
+        assert_eq!(changed.deleted, HashSet::from(["old.txt".to_string()]));
+        assert_eq!(
+            changed.values,
+            vec![ValueChange {
+                file: "config/app.json".into(),
+                key: "version".into(),
+                old: Some("1".into()),
+                new: "2".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mutate_created_files() {
+        let mut repo = InMemoryRepository::default();
+        repo.commit(CommitRequest {
+            branch: "main".into(),
+            files: HashMap::from([("old.txt".to_string(), Bytes::from("old"))]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let changed = mutate(
+            &repo,
+            "main",
+            &serde_json::from_str::<Vec<Mutation>>(
+                r#"[
+                    {"templater": "file", "file": "new.txt", "content": "hello"},
+                    {"templater": "move", "from": "new.txt", "to": "config/new.txt"},
+                    {"templater": "file", "file": "tmp.txt", "content": "scratch"},
+                    {"templater": "delete", "file": "tmp.txt"},
+                    {"templater": "file", "file": "old.txt", "content": "replaced"},
+                    {"templater": "delete", "file": "old.txt"}
+                ]"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            changed.files,
+            HashMap::from([("config/new.txt".to_string(), Bytes::from("hello"))])
+        );
+        assert!(changed.moved.is_empty());
+        assert_eq!(changed.deleted, HashSet::from(["old.txt".to_string()]));
+    }
+
+    #[test]
+    fn test_mutate_unchanged() {
+        let mut repo = InMemoryRepository::default();
+        repo.commit(CommitRequest {
+            branch: "main".into(),
+            files: HashMap::from([
+                ("app.json".to_string(), Bytes::from(r#"{"version":"1"}"#)),
+                ("app.txt".to_string(), Bytes::from("hello")),
+                (
+                    "values.yaml".to_string(),
+                    Bytes::from("# deployed by CI\nimage:\n  tag: \"1.0\" # pinned\n"),
+                ),
+                (
+                    "secret.yaml".to_string(),
+                    Bytes::from(
+                        "kind: Secret\n# rotated monthly\nstringData:\n  password: 'hunter2'\n",
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let changed = mutate(
+            &repo,
+            "main",
+            &serde_json::from_str::<Vec<Mutation>>(
+                r#"[
+                    {"templater": "json", "file": "app.json", "changes": {"version": "1"}},
+                    {"templater": "file", "file": "app.txt", "content": "hello"},
+                    {"templater": "yaml", "file": "values.yaml", "changes": {"image.tag": "1.0"}},
+                    {"templater": "kubernetes_secret", "file": "secret.yaml", "changes": {"stringData.password": "hunter2"}}
+                ]"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert!(changed.is_empty());
+    }
+
+    #[test]
+    fn test_from_assignments() {
+        let mutations = from_assignments(&[
+            "yaml:deploy/values.yaml:image.tag=1.5.0".into(),
+            "json:app.json:version=2".into(),
+            "yaml:deploy/values.yaml:env.URL=https://example.com/?a=b".into(),
+            "kubernetes-secret:secret.yaml:data.password=hunter2".into(),
+        ])
+        .unwrap();
+
+        assert_eq!(mutations.len(), 3);
+        match &mutations[0] {
+            Mutation::Yaml { file, changes } => {
+                assert_eq!(file, "deploy/values.yaml");
+                assert_eq!(
+                    changes,
+                    &HashMap::from([
+                        ("image.tag".to_string(), "1.5.0".to_string()),
+                        (
+                            "env.URL".to_string(),
+                            "https://example.com/?a=b".to_string()
+                        ),
+                    ])
+                );
+            }
+            other => panic!("unexpected mutation {other:?}"),
+        }
+        assert!(matches!(&mutations[1], Mutation::Json { file, .. } if file == "app.json"));
+        assert!(
+            matches!(&mutations[2], Mutation::KubernetesSecret { file, .. } if file == "secret.yaml")
+        );
+
+        for invalid in [
+            "yaml:values.yaml",
+            "values.yaml:image.tag=1",
+            "yaml::image.tag=1",
+            "delete:old.txt:key=1",
+        ] {
+            assert!(from_assignments(&[invalid.into()]).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mutations = serde_json::from_str::<Vec<Mutation>>(
+            r#"[
+                {"templater": "yaml", "file": "values.yaml", "changes": {"image.tag": "1"}},
+                {"templater": "dockerfile", "file": "Dockerfile", "changes": {"from.0.tag": "1"}},
+                {"templater": "kubernetes_secret", "file": "secret.yaml", "changes": {"data.tls.crt": "x"}},
+                {"templater": "move", "from": "a.txt", "to": "b.txt"},
+                {"templater": "yaml", "file": "values.yaml", "changes": {"image..tag": "1"}},
+                {"templater": "yaml", "file": "values.yaml", "changes": {}},
+                {"templater": "dockerfile", "file": "Dockerfile", "changes": {"run.0": "1"}},
+                {"templater": "kubernetes_secret", "file": "secret.yaml", "changes": {"metadata.name": "x"}},
+                {"templater": "file", "file": "new.bin", "base64": "not base64!"},
+                {"templater": "delete", "file": "../outside.txt"},
+                {"templater": "move", "from": "a.txt", "to": "a.txt"}
+            ]"#,
+        )
+        .unwrap();
+
+        let valid: Vec<bool> = mutations
+            .iter()
+            .map(|mutation| mutation.validate().is_ok())
+            .collect();
+        assert_eq!(
+            valid,
+            [true, true, true, true, false, false, false, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn test_templater_for() {
+        assert_eq!(templater_for("config/app.json"), Some("json"));
+        assert_eq!(templater_for("values.yml"), Some("yaml"));
+        assert_eq!(templater_for("docker/Dockerfile"), Some("dockerfile"));
+        assert_eq!(templater_for("README.md"), None);
+    }
+}
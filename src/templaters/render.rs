@@ -0,0 +1,77 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Deserialize;
+
+/// where a template is loaded from
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Template {
+    /// a file on the local filesystem
+    Path(PathBuf),
+    /// a file in the repository, read from the target branch
+    Repository(String),
+}
+
+/// renders a Jinja-style template, failing on undefined variables
+pub fn render(template: &Bytes, vars: &HashMap<String, serde_json::Value>) -> Result<Bytes> {
+    let template = std::str::from_utf8(template)?;
+
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+
+    let rendered = env
+        .render_str(template, vars)
+        .map_err(|err| anyhow!("could not render template: {:#}", err))?;
+    Ok(Bytes::from(rendered))
+}
+
+/// checks the template syntax without rendering it
+pub fn check(template: &Bytes) -> Result<()> {
+    let template = std::str::from_utf8(template)?;
+    Environment::new()
+        .template_from_str(template)
+        .map_err(|err| anyhow!("invalid template: {:#}", err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Bytes::from(
+            r#"name: preview-{{ mr }}
+hosts:
+{%- for host in hosts %}
+  - {{ host }}
+{%- endfor %}
+"#,
+        );
+
+        let rendered = render(
+            &template,
+            &HashMap::from([
+                ("mr".to_string(), json!(42)),
+                (
+                    "hosts".to_string(),
+                    json!(["a.example.com", "b.example.com"]),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            rendered,
+            Bytes::from("name: preview-42\nhosts:\n  - a.example.com\n  - b.example.com\n")
+        );
+
+        assert!(render(&template, &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose, Engine};
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::{collections::HashMap, fmt};
+
+/// changes to the keys of a Secret, whose values are never printed
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct SecretChanges(pub HashMap<String, String>);
+
+impl fmt::Debug for SecretChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys().map(|key| (key, "<redacted>")))
+            .finish()
+    }
+}
+
+/// sets a key of the Secret, creating it if needed
+fn patch(secret: &mut Value, path: &str, value: &str) -> Result<()> {
+    // Secret keys can contain dots (eg. tls.crt), so only the section is split off
+    let (section, key) = path
+        .split_once('.')
+        .ok_or_else(|| anyhow!("invalid Secret path {}", path))?;
+
+    let value = match section {
+        "data" => general_purpose::STANDARD.encode(value),
+        "stringData" => value.into(),
+        _ => bail!(
+            "invalid Secret path {}, expected data.* or stringData.*",
+            path
+        ),
+    };
+
+    let secret = secret
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("Secret manifest is not a mapping"))?;
+    let section = secret
+        .entry(Value::from(section))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if section.is_null() {
+        *section = Value::Mapping(Mapping::new());
+    }
+
+    section
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("could not find object path {}", path))?
+        .insert(Value::from(key), Value::from(value));
+    Ok(())
+}
+
+pub fn update_file(file: &Bytes, changes: &SecretChanges) -> Result<Bytes> {
+    let original: Value = serde_yaml::from_slice(file)?;
+
+    if original["kind"] != "Secret" {
+        bail!("not a Kubernetes Secret manifest");
+    }
+
+    // apply changes
+    let mut parsed = original.clone();
+    for (path, value) in &changes.0 {
+        patch(&mut parsed, path, value)?;
+    }
+
+    // keep the file as it is, so that unchanged files don't lose their comments and quoting
+    if parsed == original {
+        return Ok(file.clone());
+    }
+    Ok(Bytes::from(serde_yaml::to_string(&parsed)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_file() {
+        let original = Bytes::from(
+            r#"
+apiVersion: v1
+kind: Secret
+metadata:
+  name: test
+data:
+  password: Y2hhbmdlbWU=
+stringData:
+  username: changeme"#,
+        );
+
+        let changed = update_file(
+            &original,
+            &SecretChanges(HashMap::from([
+                ("data.password".to_string(), "hunter2".to_string()),
+                ("data.tls.crt".to_string(), "certificate".to_string()),
+                ("stringData.username".to_string(), "admin".to_string()),
+            ])),
+        )
+        .unwrap();
+
+        let parsed: Value = serde_yaml::from_slice(&changed).unwrap();
+        assert_eq!(parsed["data"]["password"], "aHVudGVyMg==");
+        assert_eq!(parsed["data"]["tls.crt"], "Y2VydGlmaWNhdGU=");
+        assert_eq!(parsed["stringData"]["username"], "admin");
+
+        assert!(update_file(
+            &Bytes::from("kind: ConfigMap"),
+            &SecretChanges(HashMap::from([("data.key".into(), "value".into())])),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_debug_redacts_values() {
+        let changes = SecretChanges(HashMap::from([(
+            "data.password".to_string(),
+            "hunter2".to_string(),
+        )]));
+
+        assert_eq!(
+            format!("{:?}", changes),
+            r#"{"data.password": "<redacted>"}"#
+        );
+    }
+}
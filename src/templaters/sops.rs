@@ -0,0 +1,710 @@
+use std::{io::Read, path::PathBuf, str::FromStr, time::SystemTime};
+
+use aes_gcm::{
+    aead::{consts::U32, generic_array::GenericArray, Aead, AeadCore, KeyInit, OsRng, Payload},
+    aes::Aes256,
+    AesGcm,
+};
+use age::{armor::ArmoredReader, x25519::Identity};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose, Engine};
+use bytes::Bytes;
+use regex::Regex;
+use serde_yaml::{Number, Value};
+use sha2::{Digest, Sha512};
+
+use super::secret::SecretChanges;
+
+/// SOPS uses AES-GCM with 256 bit nonces
+type Cipher = AesGcm<Aes256, U32>;
+
+const METADATA_KEY: &str = "sops";
+const DEFAULT_UNENCRYPTED_SUFFIX: &str = "_unencrypted";
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    fn separator(self) -> char {
+        match self {
+            Format::Json => '/',
+            Format::Yaml => '.',
+        }
+    }
+
+    fn parse(self, file: &Bytes) -> Result<Value> {
+        // JSON is parsed into a YAML value as well, to preserve the key order the MAC depends on
+        Ok(match self {
+            Format::Json => serde_json::from_slice(file)?,
+            Format::Yaml => serde_yaml::from_slice(file)?,
+        })
+    }
+
+    fn serialize(self, value: &Value) -> Result<Bytes> {
+        Ok(match self {
+            Format::Json => Bytes::from(serde_json::to_vec_pretty(value)?),
+            Format::Yaml => Bytes::from(serde_yaml::to_string(value)?),
+        })
+    }
+}
+
+/// loads age identities the same way sops does, from SOPS_AGE_KEY or SOPS_AGE_KEY_FILE
+pub fn identities() -> Result<Vec<Identity>> {
+    let mut keys = std::env::var("SOPS_AGE_KEY").unwrap_or_default();
+
+    let key_file = std::env::var_os("SOPS_AGE_KEY_FILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                })
+                .map(|config| config.join("sops/age/keys.txt"))
+                .filter(|path| path.exists())
+        });
+    if let Some(key_file) = key_file {
+        keys.push('\n');
+        keys.push_str(&std::fs::read_to_string(&key_file).map_err(|err| {
+            anyhow!(
+                "could not read age key file {}: {}",
+                key_file.display(),
+                err
+            )
+        })?);
+    }
+
+    let identities = keys
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Identity::from_str(line).map_err(|err| anyhow!("invalid age key: {}", err)))
+        .collect::<Result<Vec<Identity>>>()?;
+
+    if identities.is_empty() {
+        bail!("no age key found, set SOPS_AGE_KEY or SOPS_AGE_KEY_FILE");
+    }
+    Ok(identities)
+}
+
+fn key_to_string(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+/// returns the bytes SOPS encrypts for a value, along with its type
+fn to_plaintext(value: &Value) -> Option<(String, &'static str)> {
+    match value {
+        Value::String(value) => Some((value.clone(), "str")),
+        Value::Bool(value) => Some((value.to_string(), "bool")),
+        // formatted like Go's strconv.FormatFloat(value, 'f', -1, 64)
+        Value::Number(value) if value.is_f64() => Some((value.as_f64()?.to_string(), "float")),
+        Value::Number(value) => Some((value.to_string(), "int")),
+        _ => None,
+    }
+}
+
+/// returns the bytes SOPS hashes for a value when computing the MAC
+fn to_mac_bytes(value: &Value) -> Option<String> {
+    match value {
+        // booleans are hashed in their Python representation for compatibility with the original sops
+        Value::Bool(true) => Some("True".into()),
+        Value::Bool(false) => Some("False".into()),
+        value => to_plaintext(value).map(|(plaintext, _)| plaintext),
+    }
+}
+
+fn from_plaintext(plaintext: Vec<u8>, kind: &str) -> Result<Value> {
+    let plaintext = String::from_utf8(plaintext)?;
+    Ok(match kind {
+        "str" | "bytes" => Value::String(plaintext),
+        "int" => Value::Number(Number::from(plaintext.parse::<i64>()?)),
+        "float" => Value::Number(Number::from(plaintext.parse::<f64>()?)),
+        "bool" => Value::Bool(matches!(
+            plaintext.as_str(),
+            "1" | "t" | "T" | "true" | "True" | "TRUE"
+        )),
+        other => bail!("unsupported SOPS value type {}", other),
+    })
+}
+
+fn encrypt(key: &[u8], plaintext: &str, kind: &str, aad: &str) -> Result<String> {
+    let cipher = Cipher::new_from_slice(key)?;
+    let iv = Cipher::generate_nonce(&mut OsRng);
+    let mut data = cipher
+        .encrypt(
+            &iv,
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("could not encrypt value"))?;
+    let tag = data.split_off(data.len() - 16);
+
+    Ok(format!(
+        "ENC[AES256_GCM,data:{},iv:{},tag:{},type:{}]",
+        general_purpose::STANDARD.encode(data),
+        general_purpose::STANDARD.encode(iv),
+        general_purpose::STANDARD.encode(tag),
+        kind
+    ))
+}
+
+/// decrypts an `ENC[AES256_GCM,...]` string, returning None if value isn't encrypted
+fn decrypt(key: &[u8], value: &str, aad: &str) -> Result<Option<Value>> {
+    let Some(fields) = value
+        .strip_prefix("ENC[AES256_GCM,")
+        .and_then(|value| value.strip_suffix(']'))
+    else {
+        return Ok(None);
+    };
+
+    let (mut data, mut iv, mut tag, mut kind) = (None, None, None, None);
+    for field in fields.split(',') {
+        match field.split_once(':') {
+            Some(("data", value)) => data = Some(general_purpose::STANDARD.decode(value)?),
+            Some(("iv", value)) => iv = Some(general_purpose::STANDARD.decode(value)?),
+            Some(("tag", value)) => tag = Some(general_purpose::STANDARD.decode(value)?),
+            Some(("type", value)) => kind = Some(value),
+            _ => bail!("invalid SOPS encrypted value"),
+        }
+    }
+    let (Some(mut data), Some(iv), Some(tag), Some(kind)) = (data, iv, tag, kind) else {
+        bail!("invalid SOPS encrypted value");
+    };
+    if iv.len() != 32 {
+        bail!("invalid SOPS encrypted value");
+    }
+
+    data.extend(tag);
+    let plaintext = Cipher::new_from_slice(key)?
+        .decrypt(
+            GenericArray::from_slice(&iv),
+            Payload {
+                msg: &data,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("could not decrypt value, wrong key or tampered file"))?;
+
+    from_plaintext(plaintext, kind).map(Some)
+}
+
+/// the parts of the sops metadata section needed to edit a file
+struct Metadata {
+    data_key: Vec<u8>,
+    unencrypted_suffix: Option<String>,
+    encrypted_suffix: Option<String>,
+    unencrypted_regex: Option<Regex>,
+    encrypted_regex: Option<Regex>,
+    mac_only_encrypted: bool,
+}
+
+impl Metadata {
+    fn load(file: &Value, identities: &[Identity]) -> Result<Self> {
+        let sops = file
+            .get(METADATA_KEY)
+            .ok_or_else(|| anyhow!("file is not encrypted with SOPS"))?;
+
+        let string = |key: &str| sops[key].as_str().map(String::from);
+        let regex = |key: &str| string(key).map(|regex| Regex::new(&regex)).transpose();
+
+        let mut metadata = Self {
+            data_key: data_key(sops, identities)?,
+            unencrypted_suffix: string("unencrypted_suffix"),
+            encrypted_suffix: string("encrypted_suffix"),
+            unencrypted_regex: regex("unencrypted_regex")?,
+            encrypted_regex: regex("encrypted_regex")?,
+            mac_only_encrypted: sops["mac_only_encrypted"].as_bool().unwrap_or(false),
+        };
+        if metadata.unencrypted_suffix.is_none()
+            && metadata.encrypted_suffix.is_none()
+            && metadata.unencrypted_regex.is_none()
+            && metadata.encrypted_regex.is_none()
+        {
+            metadata.unencrypted_suffix = Some(DEFAULT_UNENCRYPTED_SUFFIX.into());
+        }
+        Ok(metadata)
+    }
+
+    /// whether the value at path is encrypted, following the same rules as sops
+    fn encrypts(&self, path: &[String]) -> bool {
+        let mut encrypted = true;
+        if let Some(suffix) = &self.unencrypted_suffix {
+            encrypted = !path.iter().any(|key| key.ends_with(suffix.as_str()));
+        }
+        if let Some(suffix) = &self.encrypted_suffix {
+            encrypted = path.iter().any(|key| key.ends_with(suffix.as_str()));
+        }
+        if let Some(regex) = &self.unencrypted_regex {
+            encrypted = !path.iter().any(|key| regex.is_match(key));
+        }
+        if let Some(regex) = &self.encrypted_regex {
+            encrypted = path.iter().any(|key| regex.is_match(key));
+        }
+        encrypted
+    }
+
+    /// decrypts all encrypted values of the tree in place
+    fn decrypt(&self, value: &mut Value, path: &mut Vec<String>) -> Result<()> {
+        match value {
+            Value::Mapping(mapping) => {
+                for (key, value) in mapping.iter_mut() {
+                    let key = key_to_string(key);
+                    if path.is_empty() && key == METADATA_KEY {
+                        continue;
+                    }
+                    path.push(key);
+                    self.decrypt(value, path)?;
+                    path.pop();
+                }
+            }
+            // list items share the path of the list itself
+            Value::Sequence(sequence) => {
+                for value in sequence {
+                    self.decrypt(value, path)?;
+                }
+            }
+            Value::String(encrypted) if self.encrypts(path) => {
+                if let Some(plaintext) = decrypt(&self.data_key, encrypted, &aad(path))? {
+                    *value = plaintext;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// encrypts the values of patched that differ from original, leaving the others untouched
+    fn encrypt(
+        &self,
+        output: &mut Value,
+        original: &Value,
+        patched: &Value,
+        path: &mut Vec<String>,
+    ) -> Result<()> {
+        match patched {
+            Value::Mapping(mapping) => {
+                for (key, patched) in mapping {
+                    let name = key_to_string(key);
+                    if path.is_empty() && name == METADATA_KEY {
+                        continue;
+                    }
+                    let Some(output) = output.get_mut(key) else {
+                        continue;
+                    };
+                    path.push(name);
+                    let original = original.get(key).unwrap_or(&Value::Null);
+                    self.encrypt(output, original, patched, path)?;
+                    path.pop();
+                }
+            }
+            Value::Sequence(sequence) => {
+                for (index, patched) in sequence.iter().enumerate() {
+                    let Some(output) = output.get_mut(index) else {
+                        continue;
+                    };
+                    let original = original.get(index).unwrap_or(&Value::Null);
+                    self.encrypt(output, original, patched, path)?;
+                }
+            }
+            patched if patched != original => {
+                *output = match to_plaintext(patched) {
+                    Some((plaintext, kind)) if self.encrypts(path) => {
+                        Value::String(encrypt(&self.data_key, &plaintext, kind, &aad(path))?)
+                    }
+                    _ => patched.clone(),
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// computes the MAC over the decrypted tree
+    fn mac(&self, plaintext: &Value) -> String {
+        let mut hasher = Sha512::new();
+        self.hash(plaintext, &mut vec![], &mut hasher);
+        hex::encode_upper(hasher.finalize())
+    }
+
+    fn hash(&self, value: &Value, path: &mut Vec<String>, hasher: &mut Sha512) {
+        match value {
+            Value::Mapping(mapping) => {
+                for (key, value) in mapping {
+                    let key = key_to_string(key);
+                    if path.is_empty() && key == METADATA_KEY {
+                        continue;
+                    }
+                    path.push(key);
+                    self.hash(value, path, hasher);
+                    path.pop();
+                }
+            }
+            Value::Sequence(sequence) => {
+                for value in sequence {
+                    self.hash(value, path, hasher);
+                }
+            }
+            value => {
+                if self.mac_only_encrypted && !self.encrypts(path) {
+                    return;
+                }
+                if let Some(bytes) = to_mac_bytes(value) {
+                    hasher.update(bytes);
+                }
+            }
+        }
+    }
+}
+
+/// values are authenticated together with their path
+fn aad(path: &[String]) -> String {
+    format!("{}:", path.join(":"))
+}
+
+/// decrypts the data key with the first age stanza matching one of the identities
+fn data_key(sops: &Value, identities: &[Identity]) -> Result<Vec<u8>> {
+    for recipient in sops["age"].as_sequence().into_iter().flatten() {
+        let Some(enc) = recipient["enc"].as_str() else {
+            continue;
+        };
+        let age::Decryptor::Recipients(decryptor) =
+            age::Decryptor::new(ArmoredReader::new(enc.as_bytes()))?
+        else {
+            continue;
+        };
+        let mut reader = match decryptor.decrypt(
+            identities
+                .iter()
+                .map(|identity| identity as &dyn age::Identity),
+        ) {
+            Ok(reader) => reader,
+            Err(age::DecryptError::NoMatchingKeys) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let mut key = vec![];
+        reader.read_to_end(&mut key)?;
+        return Ok(key);
+    }
+
+    bail!("none of the age keys can decrypt the SOPS data key")
+}
+
+/// decrypts the file and verifies its MAC, returning the metadata and the plaintext tree
+fn open(encrypted: &Value, identities: &[Identity]) -> Result<(Metadata, Value)> {
+    let metadata = Metadata::load(encrypted, identities)?;
+
+    let mut plaintext = encrypted.clone();
+    metadata.decrypt(&mut plaintext, &mut vec![])?;
+
+    let sops = &encrypted[METADATA_KEY];
+    let mac = match (sops["mac"].as_str(), sops["lastmodified"].as_str()) {
+        (Some(mac), Some(lastmodified)) => decrypt(&metadata.data_key, mac, lastmodified)?,
+        _ => None,
+    };
+    if mac.as_ref().and_then(Value::as_str) != Some(metadata.mac(&plaintext).as_str()) {
+        bail!("SOPS MAC mismatch, the file is corrupted or has been tampered with");
+    }
+
+    Ok((metadata, plaintext))
+}
+
+/// updates the MAC and last modification time of the sops metadata
+fn seal(metadata: &Metadata, output: &mut Value, plaintext: &Value) -> Result<()> {
+    let lastmodified = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let mac = encrypt(
+        &metadata.data_key,
+        &metadata.mac(plaintext),
+        "str",
+        &lastmodified,
+    )?;
+
+    let sops = output
+        .get_mut(METADATA_KEY)
+        .and_then(Value::as_mapping_mut)
+        .ok_or_else(|| anyhow!("file is not encrypted with SOPS"))?;
+    sops.insert("lastmodified".into(), lastmodified.into());
+    sops.insert("mac".into(), mac.into());
+    Ok(())
+}
+
+/// sets the value at the given path, as the JSON and YAML templaters do
+fn patch(base: &mut Value, path: &str, separator: char, value: &str) -> Result<()> {
+    let mut current = base;
+    for (index, part) in path.split(separator).enumerate() {
+        if index == 0 && part == METADATA_KEY {
+            bail!("the SOPS metadata cannot be changed");
+        }
+
+        // if part is numeric, treat it as array index
+        if let Ok(numeric_part) = part.parse::<usize>() {
+            current = current
+                .get_mut(numeric_part)
+                .ok_or_else(|| anyhow!("could not find index path {}", path))?;
+            continue;
+        }
+
+        // otherwise treat it as object key
+        current = current
+            .get_mut(part)
+            .ok_or_else(|| anyhow!("could not find object path {}", path))?;
+    }
+    *current = Value::from(value);
+    Ok(())
+}
+
+/// changes values of a SOPS-encrypted file, re-encrypting only them and updating the MAC.
+/// The file is re-serialised when something changes, so YAML comments are lost
+pub fn update_file(
+    file: &Bytes,
+    format: Format,
+    changes: &SecretChanges,
+    identities: &[Identity],
+) -> Result<Bytes> {
+    let encrypted = format.parse(file)?;
+    let (metadata, original) = open(&encrypted, identities)?;
+
+    // apply changes
+    let mut patched = original.clone();
+    for (path, value) in &changes.0 {
+        patch(&mut patched, path, format.separator(), value)?;
+    }
+
+    // re-encrypting would only produce a new MAC and fresh IVs, keep the file as it is
+    if patched == original {
+        return Ok(file.clone());
+    }
+
+    let mut output = encrypted;
+    metadata.encrypt(&mut output, &original, &patched, &mut vec![])?;
+    seal(&metadata, &mut output, &patched)?;
+
+    format.serialize(&output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Write};
+
+    use age::armor::{ArmoredWriter, Format as ArmorFormat};
+
+    use super::*;
+
+    /// encrypts a plaintext file the way `sops --encrypt --age` would
+    fn encrypt_file(plaintext: &str, format: Format, identity: &Identity) -> Bytes {
+        let data_key: Vec<u8> = Cipher::generate_key(&mut OsRng).to_vec();
+
+        let mut enc = vec![];
+        let armor = ArmoredWriter::wrap_output(&mut enc, ArmorFormat::AsciiArmor).unwrap();
+        let mut writer = age::Encryptor::with_recipients(vec![Box::new(identity.to_public())])
+            .unwrap()
+            .wrap_output(armor)
+            .unwrap();
+        writer.write_all(&data_key).unwrap();
+        writer.finish().unwrap().finish().unwrap();
+
+        let plaintext = format.parse(&Bytes::from(plaintext.to_string())).unwrap();
+        let mut output = plaintext.clone();
+        output.as_mapping_mut().unwrap().insert(
+            METADATA_KEY.into(),
+            serde_yaml::from_str(&format!(
+                "{{age: [{{recipient: {}, enc: {:?}}}], unencrypted_suffix: _unencrypted}}",
+                identity.to_public(),
+                String::from_utf8(enc).unwrap()
+            ))
+            .unwrap(),
+        );
+
+        let metadata = Metadata::load(&output, &[identity.clone()]).unwrap();
+        metadata
+            .encrypt(&mut output, &Value::Null, &plaintext, &mut vec![])
+            .unwrap();
+        seal(&metadata, &mut output, &plaintext).unwrap();
+        format.serialize(&output).unwrap()
+    }
+
+    /// fixtures encrypted for the age key in keys.txt, in the layout written by sops 3.8.
+    /// They were produced independently of this module (AES-GCM values with path AAD,
+    /// SHA-512 MAC, age-wrapped data key), to catch mistakes a round trip would hide
+    const FIXTURE_KEY: &str = include_str!("testdata/sops/keys.txt");
+    const FIXTURE_YAML: &[u8] = include_bytes!("testdata/sops/secrets.enc.yaml");
+    const FIXTURE_JSON: &[u8] = include_bytes!("testdata/sops/secrets.enc.json");
+
+    fn fixture_identity() -> Identity {
+        let key = FIXTURE_KEY
+            .lines()
+            .find(|line| !line.starts_with('#'))
+            .unwrap();
+        Identity::from_str(key).unwrap()
+    }
+
+    #[test]
+    fn test_fixture() {
+        let identity = fixture_identity();
+        let original = Bytes::from_static(FIXTURE_YAML);
+        let encrypted = Format::Yaml.parse(&original).unwrap();
+
+        let (_, plaintext) = open(&encrypted, &[identity.clone()]).unwrap();
+        assert_eq!(plaintext["image"]["tag"], "1.0");
+        assert_eq!(plaintext["replicas"], 2);
+        assert_eq!(plaintext["enabled"], true);
+        assert_eq!(plaintext["ratio"], 0.5);
+        assert_eq!(plaintext["hosts"][1], "example.org");
+        assert_eq!(plaintext["name_unencrypted"], "visible");
+
+        let changed = update_file(
+            &original,
+            Format::Yaml,
+            &SecretChanges(HashMap::from([("image.tag".into(), "2.0".into())])),
+            &[identity.clone()],
+        )
+        .unwrap();
+
+        // the MAC is still valid after editing
+        let parsed = Format::Yaml.parse(&changed).unwrap();
+        let (_, plaintext) = open(&parsed, &[identity]).unwrap();
+        assert_eq!(plaintext["image"]["tag"], "2.0");
+
+        // untouched values keep their ciphertext
+        for key in ["replicas", "enabled", "ratio", "hosts"] {
+            assert_eq!(parsed[key], encrypted[key]);
+        }
+        assert_ne!(parsed["image"]["tag"], encrypted["image"]["tag"]);
+        assert_eq!(parsed["sops"]["age"], encrypted["sops"]["age"]);
+        assert_ne!(parsed["sops"]["mac"], encrypted["sops"]["mac"]);
+    }
+
+    #[test]
+    fn test_json_fixture() {
+        let identity = fixture_identity();
+        let original = Bytes::from_static(FIXTURE_JSON);
+        let encrypted = Format::Json.parse(&original).unwrap();
+
+        let changed = update_file(
+            &original,
+            Format::Json,
+            &SecretChanges(HashMap::from([(
+                "database/password".into(),
+                "hunter2".into(),
+            )])),
+            &[identity.clone()],
+        )
+        .unwrap();
+
+        let parsed = Format::Json.parse(&changed).unwrap();
+        let (_, plaintext) = open(&parsed, &[identity]).unwrap();
+        assert_eq!(plaintext["database"]["user"], "app");
+        assert_eq!(plaintext["database"]["password"], "hunter2");
+        assert_eq!(parsed["database"]["user"], encrypted["database"]["user"]);
+    }
+
+    #[test]
+    fn test_update_file() {
+        let identity = Identity::generate();
+        let original = encrypt_file(
+            r#"
+image:
+  tag: "1.0"
+replicas: 2
+enabled: true
+hosts:
+  - example.com
+name_unencrypted: visible"#,
+            Format::Yaml,
+            &identity,
+        );
+
+        let encrypted: Value = serde_yaml::from_slice(&original).unwrap();
+        assert!(encrypted["image"]["tag"]
+            .as_str()
+            .unwrap()
+            .starts_with("ENC[AES256_GCM,"));
+        assert_eq!(encrypted["name_unencrypted"], "visible");
+
+        let changed = update_file(
+            &original,
+            Format::Yaml,
+            &SecretChanges(HashMap::from([
+                ("image.tag".to_string(), "2.0".to_string()),
+                ("name_unencrypted".to_string(), "changed".to_string()),
+            ])),
+            &[identity.clone()],
+        )
+        .unwrap();
+
+        let parsed: Value = serde_yaml::from_slice(&changed).unwrap();
+        // untouched values keep their ciphertext
+        assert_eq!(parsed["replicas"], encrypted["replicas"]);
+        assert_ne!(parsed["image"]["tag"], encrypted["image"]["tag"]);
+        assert_eq!(parsed["name_unencrypted"], "changed");
+
+        let (_, plaintext) = open(&parsed, &[identity.clone()]).unwrap();
+        assert_eq!(plaintext["image"]["tag"], "2.0");
+        assert_eq!(plaintext["replicas"], 2);
+        assert_eq!(plaintext["enabled"], true);
+        assert_eq!(plaintext["hosts"][0], "example.com");
+
+        // no-op changes leave the file untouched
+        let unchanged = update_file(
+            &changed,
+            Format::Yaml,
+            &SecretChanges(HashMap::from([("image.tag".into(), "2.0".into())])),
+            &[identity],
+        )
+        .unwrap();
+        assert_eq!(unchanged, changed);
+
+        // other keys cannot decrypt the file
+        assert!(update_file(
+            &changed,
+            Format::Yaml,
+            &SecretChanges(HashMap::new()),
+            &[Identity::generate()],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_update_json_file() {
+        let identity = Identity::generate();
+        let original = encrypt_file(
+            r#"{"database": {"password": "changeme"}}"#,
+            Format::Json,
+            &identity,
+        );
+
+        let changed = update_file(
+            &original,
+            Format::Json,
+            &SecretChanges(HashMap::from([(
+                "database/password".to_string(),
+                "hunter2".to_string(),
+            )])),
+            &[identity.clone()],
+        )
+        .unwrap();
+
+        let (_, plaintext) = open(
+            &serde_json::from_slice(&changed).unwrap(),
+            &[identity.clone()],
+        )
+        .unwrap();
+        assert_eq!(plaintext["database"]["password"], "hunter2");
+
+        // swapping in an older ciphertext is caught by the MAC
+        let original: Value = serde_json::from_slice(&original).unwrap();
+        let mut tampered: Value = serde_json::from_slice(&changed).unwrap();
+        tampered["database"]["password"] = original["database"]["password"].clone();
+        assert!(open(&tampered, &[identity]).is_err());
+    }
+}
@@ -24,14 +24,41 @@ fn patch(base: &mut Value, path: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// returns the scalar value at the given path, if any
+pub fn value(file: &Bytes, path: &str) -> Result<Option<String>> {
+    let parsed: Value = serde_yaml::from_slice(file)?;
+    let mut current = &parsed;
+    for part in path.split('.') {
+        let next = match part.parse::<usize>() {
+            Ok(numeric_part) => current.get(numeric_part),
+            Err(_) => current.get(part),
+        };
+        let Some(next) = next else {
+            return Ok(None);
+        };
+        current = next;
+    }
+    Ok(match current {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    })
+}
+
 pub fn update_file(file: &Bytes, changes: &HashMap<String, String>) -> Result<Bytes> {
-    let mut parsed: Value = serde_yaml::from_slice(file)?;
+    let original: Value = serde_yaml::from_slice(file)?;
 
     // apply changes
+    let mut parsed = original.clone();
     for (path, value) in changes {
         patch(&mut parsed, path, value)?;
     }
 
+    // keep the file as it is, so that unchanged files don't lose their comments and quoting
+    if parsed == original {
+        return Ok(file.clone());
+    }
     Ok(Bytes::from(serde_yaml::to_string(&parsed)?))
 }
 
@@ -64,4 +91,15 @@ something:
         assert_eq!(parsed["test"]["nested"]["key"], "changed");
         assert_eq!(parsed["something"][0], "also changed");
     }
+
+    #[test]
+    fn test_value() {
+        let file = Bytes::from("image:\n  tag: 1.4.2\n  replicas: 3\nhosts:\n  - example.com\n");
+
+        assert_eq!(value(&file, "image.tag").unwrap(), Some("1.4.2".into()));
+        assert_eq!(value(&file, "image.replicas").unwrap(), Some("3".into()));
+        assert_eq!(value(&file, "hosts.0").unwrap(), Some("example.com".into()));
+        assert_eq!(value(&file, "image").unwrap(), None);
+        assert_eq!(value(&file, "missing.key").unwrap(), None);
+    }
 }